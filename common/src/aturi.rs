@@ -0,0 +1,284 @@
+//! AT-URI parsing, following the restricted syntax from the atproto spec
+//!
+//! `at://<authority>[/<collection>[/<rkey>]]`, where the authority is a DID or
+//! a handle, the collection is an NSID, and there is no query or fragment.
+use std::fmt;
+use std::str::FromStr;
+
+const MAX_URI_LEN: usize = 8 * 1024;
+const MAX_DID_LEN: usize = 2 * 1024;
+const MAX_HANDLE_LEN: usize = 253;
+const MAX_NSID_LEN: usize = 317;
+const MAX_RKEY_LEN: usize = 512;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, PartialEq)]
+pub enum AtUri {
+    /// the authority may be a DID or a handle
+    Did(String),
+    DidCollection(String, String),
+    DidCollectionKey(String, String, String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AtUriError {
+    TooLong(usize),
+    MissingScheme,
+    QueryOrFragment,
+    EmptySegment,
+    TooManySegments,
+    BadAuthority(String),
+    BadDid(String),
+    BadHandle(String),
+    BadCollection(String),
+    BadRkey(String),
+}
+
+impl fmt::Display for AtUriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtUriError::TooLong(n) => write!(f, "at-uri is {n} bytes, max is {MAX_URI_LEN}"),
+            AtUriError::MissingScheme => write!(f, "at-uri must start with at://"),
+            AtUriError::QueryOrFragment => write!(f, "at-uri must not have a query or fragment"),
+            AtUriError::EmptySegment => write!(f, "at-uri has an empty path segment"),
+            AtUriError::TooManySegments => write!(f, "at-uri has more than collection/rkey in its path"),
+            AtUriError::BadAuthority(a) => write!(f, "at-uri authority {a:?} is neither a did nor a handle"),
+            AtUriError::BadDid(d) => write!(f, "invalid did {d:?}"),
+            AtUriError::BadHandle(h) => write!(f, "invalid handle {h:?}"),
+            AtUriError::BadCollection(c) => write!(f, "invalid collection nsid {c:?}"),
+            AtUriError::BadRkey(r) => write!(f, "invalid record key {r:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AtUriError {}
+
+/// one dns label: alphanumeric and hyphens, not starting or ending with a hyphen
+fn is_label(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_LABEL_LEN
+        && s.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        && !s.starts_with('-')
+        && !s.ends_with('-')
+}
+
+pub fn is_valid_handle(s: &str) -> bool {
+    if s.len() > MAX_HANDLE_LEN {
+        return false
+    }
+    let labels: Vec<_> = s.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|l| is_label(l))
+        // the tld can't start with a digit
+        && labels.last().is_some_and(|tld| tld.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+fn is_plc_id(s: &str) -> bool {
+    s.len() == 24 && s.bytes().all(|c| matches!(c, b'a'..=b'z' | b'2'..=b'7'))
+}
+
+/// a did:web host, with an optional percent-encoded port (paths aren't supported in atproto)
+fn is_web_id(s: &str) -> bool {
+    let (host, port) = match s.split_once("%3A") {
+        Some((host, port)) => (host, Some(port)),
+        None => (s, None),
+    };
+    let host_ok = host == "localhost" || is_valid_handle(host);
+    let port_ok = port.is_none_or(|p| !p.is_empty() && p.len() <= 5 && p.bytes().all(|c| c.is_ascii_digit()));
+    host_ok && port_ok
+}
+
+pub fn is_valid_did(s: &str) -> bool {
+    if s.len() > MAX_DID_LEN {
+        return false
+    }
+    let Some((method, id)) = s.strip_prefix("did:").and_then(|rest| rest.split_once(':')) else {
+        return false
+    };
+    match method {
+        "plc" => is_plc_id(id),
+        "web" => is_web_id(id),
+        _ => {
+            !method.is_empty()
+                && method.bytes().all(|c| c.is_ascii_lowercase())
+                && !id.is_empty()
+                && id.bytes().all(|c| c.is_ascii_alphanumeric() || b"._:%-".contains(&c))
+                && !id.ends_with([':', '%'])
+        }
+    }
+}
+
+pub fn is_valid_nsid(s: &str) -> bool {
+    if s.len() > MAX_NSID_LEN {
+        return false
+    }
+    let segments: Vec<_> = s.split('.').collect();
+    let Some((name, domain)) = segments.split_last() else {
+        return false
+    };
+    domain.len() >= 2
+        && domain.iter().all(|l| is_label(l))
+        && !domain[0].starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name.len() <= MAX_LABEL_LEN
+        && name.bytes().all(|c| c.is_ascii_alphanumeric())
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+pub fn is_valid_rkey(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_RKEY_LEN
+        && s != "."
+        && s != ".."
+        && s.bytes().all(|c| c.is_ascii_alphanumeric() || b"._:~-".contains(&c))
+}
+
+impl AtUri {
+    /// The DID or handle that the uri points into
+    pub fn authority(&self) -> &str {
+        match self {
+            AtUri::Did(a) | AtUri::DidCollection(a, _) | AtUri::DidCollectionKey(a, _, _) => a,
+        }
+    }
+
+    pub fn has_did_authority(&self) -> bool {
+        self.authority().starts_with("did:")
+    }
+}
+
+impl FromStr for AtUri {
+    type Err = AtUriError;
+    fn from_str(s: &str) -> Result<Self, AtUriError> {
+        if s.len() > MAX_URI_LEN {
+            return Err(AtUriError::TooLong(s.len()))
+        }
+        let Some(uri) = s.strip_prefix("at://") else {
+            return Err(AtUriError::MissingScheme)
+        };
+        if uri.contains(['?', '#']) {
+            return Err(AtUriError::QueryOrFragment)
+        }
+        let mut segments = uri.split('/');
+        let authority = segments.next().unwrap_or_default();
+        let collection = segments.next();
+        let rkey = segments.next();
+        if segments.next().is_some() {
+            return Err(AtUriError::TooManySegments)
+        }
+        if collection == Some("") || rkey == Some("") {
+            return Err(AtUriError::EmptySegment)
+        }
+
+        if authority.starts_with("did:") {
+            if !is_valid_did(authority) {
+                return Err(AtUriError::BadDid(authority.to_string()))
+            }
+        } else if authority.contains('.') {
+            if !is_valid_handle(authority) {
+                return Err(AtUriError::BadHandle(authority.to_string()))
+            }
+        } else {
+            return Err(AtUriError::BadAuthority(authority.to_string()))
+        }
+        let authority = authority.to_string();
+
+        let Some(collection) = collection else {
+            return Ok(AtUri::Did(authority))
+        };
+        if !is_valid_nsid(collection) {
+            return Err(AtUriError::BadCollection(collection.to_string()))
+        }
+        let collection = collection.to_string();
+
+        let Some(rkey) = rkey else {
+            return Ok(AtUri::DidCollection(authority, collection))
+        };
+        if !is_valid_rkey(rkey) {
+            return Err(AtUriError::BadRkey(rkey.to_string()))
+        }
+        Ok(AtUri::DidCollectionKey(authority, collection, rkey.to_string()))
+    }
+}
+
+impl fmt::Display for AtUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtUri::Did(did) => write!(f, "at://{did}"),
+            AtUri::DidCollection(did, col) => write!(f, "at://{did}/{col}"),
+            AtUri::DidCollectionKey(did, col, rkey) => write!(f, "at://{did}/{col}/{rkey}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_at_uri_did() {
+        let uri = "at://did:plc:hdhoaan3xa3jiuq4fg4mefid";
+        let did = "did:plc:hdhoaan3xa3jiuq4fg4mefid".to_string();
+        let parsed: AtUri = uri.parse().unwrap();
+        assert_eq!(parsed, AtUri::Did(did));
+        assert_eq!(parsed.to_string(), uri);
+    }
+
+    #[test]
+    fn test_at_uri_did_col() {
+        let uri = "at://did:plc:hdhoaan3xa3jiuq4fg4mefid/app.bsky.actor.profile";
+        let did = "did:plc:hdhoaan3xa3jiuq4fg4mefid".to_string();
+        let col = "app.bsky.actor.profile".to_string();
+        let parsed: AtUri = uri.parse().unwrap();
+        assert_eq!(parsed, AtUri::DidCollection(did, col));
+        assert_eq!(parsed.to_string(), uri);
+    }
+
+    #[test]
+    fn test_at_uri_did_col_rkey() {
+        let uri = "at://did:plc:hdhoaan3xa3jiuq4fg4mefid/app.bsky.feed.like/3ld53lnvvhc2w";
+        let did = "did:plc:hdhoaan3xa3jiuq4fg4mefid".to_string();
+        let col = "app.bsky.feed.like".to_string();
+        let rkey = "3ld53lnvvhc2w".to_string();
+        let parsed: AtUri = uri.parse().unwrap();
+        assert_eq!(parsed, AtUri::DidCollectionKey(did, col, rkey));
+        assert_eq!(parsed.to_string(), uri);
+    }
+
+    #[test]
+    fn test_at_uri_other_authorities() {
+        for uri in [
+            "at://did:web:example.com/app.bsky.feed.post/3ld53lnvvhc2w",
+            "at://did:web:localhost%3A2583/app.bsky.actor.profile/self",
+            "at://alice.bsky.social/app.bsky.feed.post/3ld53lnvvhc2w",
+        ] {
+            let parsed: AtUri = uri.parse().unwrap();
+            assert_eq!(parsed.to_string(), uri);
+        }
+        let parsed: AtUri = "at://alice.bsky.social".parse().unwrap();
+        assert!(!parsed.has_did_authority());
+    }
+
+    #[test]
+    fn test_at_uri_rejects() {
+        let did = "did:plc:hdhoaan3xa3jiuq4fg4mefid";
+        let long_rkey = "a".repeat(MAX_RKEY_LEN + 1);
+        for (uri, err) in [
+            ("https://bsky.app".to_string(), AtUriError::MissingScheme),
+            (format!("at://{did}/app.bsky.feed.post/3ld53lnvvhc2w?x=1"), AtUriError::QueryOrFragment),
+            (format!("at://{did}/app.bsky.feed.post/3ld53lnvvhc2w#frag"), AtUriError::QueryOrFragment),
+            (format!("at://{did}/app.bsky.feed.post/"), AtUriError::EmptySegment),
+            (format!("at://{did}/app.bsky.feed.post/a/b"), AtUriError::TooManySegments),
+            ("at://nope".to_string(), AtUriError::BadAuthority("nope".to_string())),
+            ("at://did:plc:short".to_string(), AtUriError::BadDid("did:plc:short".to_string())),
+            ("at://did:web:ex ample.com".to_string(), AtUriError::BadDid("did:web:ex ample.com".to_string())),
+            ("at://alice.123".to_string(), AtUriError::BadHandle("alice.123".to_string())),
+            (format!("at://{did}/feed.post"), AtUriError::BadCollection("feed.post".to_string())),
+            (format!("at://{did}/app.bsky.3post"), AtUriError::BadCollection("app.bsky.3post".to_string())),
+            (format!("at://{did}/app.bsky.feed.post/.."), AtUriError::BadRkey("..".to_string())),
+            (format!("at://{did}/app.bsky.feed.post/a+b"), AtUriError::BadRkey("a+b".to_string())),
+            (format!("at://{did}/app.bsky.feed.post/{long_rkey}"), AtUriError::BadRkey(long_rkey.clone())),
+        ] {
+            assert_eq!(uri.parse::<AtUri>(), Err(err), "{uri}");
+        }
+    }
+}
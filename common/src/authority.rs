@@ -0,0 +1,143 @@
+//! every like on any record of one DID, for notifications and account stats
+//!
+//! the `forward` and `did-prefixed` layouts keep an author's subject keys under
+//! one [`Layout::authority_prefix`](crate::Layout::authority_prefix), so a scan
+//! over it streams all of their likes without knowing which records they have.
+//! whatever rows a backend keeps under a subject key (a liker list, a page
+//! header and its pages, or a key per like) come out as the same [`Like`]s, in
+//! key order. rows that aren't likes, like rocks' listing keys, are skipped.
+use anyhow::{anyhow, Result};
+use crate::encoding::split_record;
+use crate::{listing, Format};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Like {
+    /// the liked record
+    pub uri: String,
+    /// the encoded liker entry
+    pub liker: Vec<u8>,
+}
+
+/// Where to scan for a did's subjects, for `collection` only if given. errors
+/// for layouts that scatter an author's records.
+pub fn prefix(format: Format, did: &str, collection: Option<&str>) -> Result<Vec<u8>> {
+    format.layout.authority_prefix(format.encoding, did, collection)
+}
+
+/// Stream the likes from raw rows scanned forward from the start of `prefix`,
+/// stopping at its end. only likes on `collection` come back if it's given,
+/// since it can't always be part of the prefix.
+///
+/// `value` decodes each liker list or page, for stores that compress them.
+pub fn likes<'a>(
+    format: Format,
+    prefix: Vec<u8>,
+    collection: Option<&'a str>,
+    rows: impl IntoIterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a,
+    mut value: impl FnMut(Vec<u8>) -> Result<Vec<u8>> + 'a,
+) -> impl Iterator<Item = Result<Like>> + 'a {
+    rows.into_iter()
+        .take_while(move |row| row.as_ref().map_or(true, |(k, _)| k.starts_with(&prefix)))
+        .flat_map(move |row| match row.and_then(|(k, v)| row_likes(format, collection, k, v, &mut value)) {
+            Ok(likes) => likes.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        })
+}
+
+/// The likes in one row under a subject key
+fn row_likes(
+    format: Format,
+    collection: Option<&str>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    decode: &mut impl FnMut(Vec<u8>) -> Result<Vec<u8>>,
+) -> Result<Vec<Like>> {
+    let (uri, n) = format.layout.decode_subject(format.encoding, &key)?;
+    if collection.is_some_and(|c| split_record(&uri).is_none_or(|(_, found, _)| found != c)) {
+        return Ok(vec![])
+    }
+    let likers = match key.get(n) {
+        // a page header
+        None if format.paging.is_some() => return Ok(vec![]),
+        None | Some(b'#') => decode(value)?,
+        Some(b'!') => return Ok(vec![Like { uri, liker: key[n + 1..].to_vec() }]),
+        Some(&listing::LISTING_SEP) => return Ok(vec![]),
+        Some(b) => return Err(anyhow!("unexpected byte {b:#04x} after the subject key for {uri}")),
+    };
+    Ok(format.encoding
+        .split_likers(&likers)?
+        .into_iter()
+        .map(|liker| Like { uri: uri.clone(), liker: liker.to_vec() })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::{self, Header, Paging};
+    use crate::{Encoding, Layout};
+
+    const DID: &str = "did:plc:iyr4nadkkq2toocambsr3inz";
+    const POST: &str = "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l";
+    const REPOST: &str = "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.repost/3ld53lnvvhc2w";
+    const OTHER: &str = "at://did:plc:iyr4nadkkq2toocambsr3inzz/app.bsky.feed.post/3lccjpbhjck2l";
+
+    fn scan(format: Format, collection: Option<&str>, mut rows: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<(String, String)> {
+        rows.sort();
+        let prefix = prefix(format, DID, collection).unwrap();
+        let start = rows.iter().position(|(k, _)| k >= &prefix).unwrap_or(rows.len());
+        likes(format, prefix, collection, rows.into_iter().skip(start).map(Ok), Ok)
+            .map(|like| {
+                let like = like.unwrap();
+                (like.uri, format.encoding.likers_text(&like.liker).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_like_keys_and_lists() {
+        for encoding in [Encoding::Text, Encoding::Tid, Encoding::Compact] {
+            for layout in [Layout::Forward, Layout::DidPrefixed] {
+                let format = Format { encoding, layout, listing: true, ..Default::default() };
+                let a = format.liker("did:plc:hdhoaan3xa3jiuq4fg4mefid", "3ld53lnvvhc2w");
+                let b = format.liker("did:web:example.com", "self");
+                let rows = vec![
+                    (format.like_key(POST, "did:plc:hdhoaan3xa3jiuq4fg4mefid", "3ld53lnvvhc2w"), vec![]),
+                    (format.listing_key(POST, "did:plc:hdhoaan3xa3jiuq4fg4mefid", "3ld53lnvvhc2w"), vec![]),
+                    (format.subject_key(REPOST), format.encoding.join(None, [&a[..], &b[..]])),
+                    (format.subject_key(OTHER), a.clone()),
+                ];
+                let mut found = scan(format, None, rows.clone());
+                found.sort();
+                assert_eq!(found, [
+                    (POST.to_string(), "did:plc:hdhoaan3xa3jiuq4fg4mefid!3ld53lnvvhc2w".to_string()),
+                    (REPOST.to_string(), "did:plc:hdhoaan3xa3jiuq4fg4mefid!3ld53lnvvhc2w".to_string()),
+                    (REPOST.to_string(), "did:web:example.com!self".to_string()),
+                ], "{layout} {encoding}");
+                let reposts = scan(format, Some("app.bsky.feed.repost"), rows);
+                assert_eq!(reposts.len(), 2, "{layout} {encoding}");
+                assert!(reposts.iter().all(|(uri, _)| uri == REPOST));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pages_skip_header() {
+        let paging = Paging { page_size: 2 };
+        let format = Format { encoding: Encoding::Compact, paging: Some(paging), ..Default::default() };
+        let key = format.subject_key(POST);
+        let likers: Vec<_> = ["3ld53lnvvhc2w", "3ld53lnvvhc2x", "3ld53lnvvhc2y"]
+            .iter()
+            .map(|rkey| format.liker("did:plc:hdhoaan3xa3jiuq4fg4mefid", rkey))
+            .collect();
+        let header = Header { count: 3, pages: 2 };
+        let rows = vec![
+            (key.clone(), header.to_bytes().to_vec()),
+            (paging::page_key(&key, 0), format.encoding.join(None, likers[..2].iter().map(|l| &l[..]))),
+            (paging::page_key(&key, 1), likers[2].clone()),
+        ];
+        let found = scan(format, None, rows);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[2].1, "did:plc:hdhoaan3xa3jiuq4fg4mefid!3ld53lnvvhc2y");
+    }
+}
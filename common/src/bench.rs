@@ -0,0 +1,638 @@
+//! bits of the read benchmarks shared by every backend
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use tinyjson::JsonValue;
+use crate::{authority, cli, counts, forward, Format};
+use crate::encoding::split_record;
+use crate::histogram::Timings;
+use crate::phase::{Phase, Phases};
+use crate::listing::{self, Cursor, Order, Page};
+use crate::verify::{self, Kind, Mismatches};
+
+/// What the `read` binaries measure, from `--mode`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// fetch each subject's whole liker list, timed by like count
+    #[default]
+    Full,
+    /// page through each subject with `--limit` and `--order`, timed by page depth
+    List,
+    /// read each subject's maintained count, timed by like count
+    Count,
+    /// ask whether dids liked each subject, timed by like count for hits and misses
+    HasLiked,
+    /// page through each sampled did's likes with `--limit` and `--order`, timed by page depth
+    Forward,
+    /// fetch whole liker lists `--batch-size` subjects at a time, timed per batch and per key
+    Batch,
+    /// stream every like on each sampled author's records, timed by like count
+    Authority,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "full" => Ok(Mode::Full),
+            "list" => Ok(Mode::List),
+            "count" => Ok(Mode::Count),
+            "has-liked" => Ok(Mode::HasLiked),
+            "forward" => Ok(Mode::Forward),
+            "batch" => Ok(Mode::Batch),
+            "authority" => Ok(Mode::Authority),
+            _ => Err(anyhow!("unknown mode {s:?}, expected full, list, count, has-liked, forward, batch or authority")),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Full => write!(f, "full"),
+            Mode::List => write!(f, "list"),
+            Mode::Count => write!(f, "count"),
+            Mode::HasLiked => write!(f, "has-liked"),
+            Mode::Forward => write!(f, "forward"),
+            Mode::Batch => write!(f, "batch"),
+            Mode::Authority => write!(f, "authority"),
+        }
+    }
+}
+
+pub const DEFAULT_LIST_LIMIT: usize = 25;
+pub const DEFAULT_PROBES: usize = 10;
+pub const DEFAULT_HIT_RATE: f64 = 0.5;
+/// about how many posts a feed page shows
+pub const DEFAULT_BATCH_SIZE: usize = 40;
+
+/// Each pass's timings by section, written as JSON to `--histograms PATH` at
+/// the end of the run with every section also merged across the passes of
+/// each phase
+pub struct Export {
+    mode: Mode,
+    path: Option<String>,
+    passes: Vec<(Phase, Vec<(&'static str, Timings)>)>,
+}
+
+impl Export {
+    pub fn from_args(mode: Mode) -> Result<Self> {
+        Ok(Export { mode, path: cli::opt("histograms")?, passes: vec![] })
+    }
+
+    /// Print a pass's sections and keep them for the export
+    pub fn push(&mut self, phase: Phase, sections: Vec<(&'static str, Timings)>) {
+        for (name, timings) in &sections {
+            if sections.len() > 1 {
+                println!("{name}");
+            }
+            timings.print();
+        }
+        self.passes.push((phase, sections));
+    }
+
+    pub fn write(self) -> Result<()> {
+        let Some(path) = self.path else {
+            return Ok(())
+        };
+        let sections = |sections: &[(&str, Timings)]| JsonValue::Object(
+            sections.iter().map(|(name, timings)| (name.to_string(), timings.to_json())).collect());
+        let mut merged: HashMap<String, Vec<(&str, Timings)>> = HashMap::new();
+        for (phase, pass) in &self.passes {
+            let merged = merged.entry(phase.to_string()).or_default();
+            for (name, timings) in pass {
+                match merged.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, m)) => m.merge(timings),
+                    None => merged.push((name, timings.clone())),
+                }
+            }
+        }
+        let passes = self.passes
+            .iter()
+            .map(|(phase, pass)| JsonValue::Object(HashMap::from([
+                ("phase".to_string(), JsonValue::String(phase.to_string())),
+                ("sections".to_string(), sections(pass)),
+            ])))
+            .collect();
+        let json = JsonValue::Object(HashMap::from([
+            ("mode".to_string(), JsonValue::String(self.mode.to_string())),
+            ("passes".to_string(), JsonValue::Array(passes)),
+            ("merged".to_string(), JsonValue::Object(
+                merged.iter().map(|(phase, merged)| (phase.clone(), sections(merged))).collect())),
+        ]));
+        std::fs::write(&path, json.stringify()?)?;
+        println!("histograms written to {path}");
+        Ok(())
+    }
+}
+
+/// Page through every sampled subject with `--order` and `--limit`, timing
+/// each page by its depth and checking the listing against the samples.
+///
+/// `list` gets the subject key, and returns one page from the cursor on.
+pub fn run_list(
+    subjects_path: &str,
+    format: Format,
+    phases: &Phases,
+    mut list: impl FnMut(&[u8], Order, usize, Option<&Cursor>) -> Result<Page>,
+) -> Result<()> {
+    if !format.listing {
+        return Err(anyhow!("list mode needs a db ingested with --listing (and --listing here too)"))
+    }
+    let order: Order = cli::opt_or_default("order")?;
+    let limit: usize = cli::opt("limit")?.unwrap_or(DEFAULT_LIST_LIMIT);
+    println!("order: {order}, limit: {limit}");
+
+    let mut export = Export::from_args(Mode::List)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let mut mismatches = Mismatches::default();
+        let reader = io::BufReader::new(File::open(subjects_path)?);
+
+        let mut total = Duration::from_secs(0);
+        let mut times = Timings::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            let Some((uri, likers)) = line.split_once('|') else {
+                return Err(anyhow!("failed to split input"))
+            };
+            let key = format.subject_key(uri);
+
+            let mut listed = vec![];
+            let mut cursor = None;
+            for depth in 0.. {
+                let t0 = Instant::now();
+                let page = list(&key, order, limit, cursor.as_ref())?;
+                let d = t0.elapsed();
+
+                total += d;
+                times.record(depth, d);
+
+                listed.extend(page.likers);
+                let Some(next) = page.cursor else {
+                    break
+                };
+                cursor = Some(next);
+            }
+
+            let likers: Vec<_> = likers.split(';').collect();
+            mismatches.record(uri, verify::classify(&listed, &listing::expected_order(&likers, order)?));
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
+
+/// Read every sampled subject's maintained count, timing each by its like
+/// count and checking it against the sampled likers that weren't unliked.
+///
+/// `count` gets the subject key, and `unliked` the liker key of a sampled like.
+pub fn run_count(
+    subjects_path: &str,
+    format: Format,
+    phases: &Phases,
+    mut count: impl FnMut(&[u8]) -> Result<i64>,
+    mut unliked: impl FnMut(&[u8]) -> Result<bool>,
+) -> Result<()> {
+    if !format.counts {
+        return Err(anyhow!("count mode needs a db ingested with --counts (and --counts here too)"))
+    }
+
+    let mut export = Export::from_args(Mode::Count)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let mut mismatches = Mismatches::default();
+        let reader = io::BufReader::new(File::open(subjects_path)?);
+
+        let mut total = Duration::from_secs(0);
+        let mut times = Timings::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            let Some((uri, likers)) = line.split_once('|') else {
+                return Err(anyhow!("failed to split input"))
+            };
+            let key = format.subject_key(uri);
+
+            let t0 = Instant::now();
+            let found = count(&key)?;
+            let d = t0.elapsed();
+
+            total += d;
+            times.record(likers.split(';').count(), d);
+
+            let live = counts::live(likers, |did, rkey| unliked(&format.liker(did, rkey)))?;
+            mismatches.record(uri, verify::classify_count(found, live));
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
+
+/// The liker keys of every sampled like that `unliked` says was unliked.
+///
+/// has-liked and forward runs check their answers against these, so read
+/// them through a throwaway open of the db and close it before opening the
+/// one that's timed, or the cold pass starts on warm caches.
+pub fn sampled_unlikes(
+    subjects_path: &str,
+    format: Format,
+    mut unliked: impl FnMut(&[u8]) -> Result<bool>,
+) -> Result<HashSet<Vec<u8>>> {
+    let mut unlikes = HashSet::new();
+    for line in io::BufReader::new(File::open(subjects_path)?).lines() {
+        let line = line?;
+        let Some((_, likers)) = line.split_once('|') else {
+            return Err(anyhow!("failed to split input"))
+        };
+        for liker in likers.split(';') {
+            let (did, rkey) = liker.split_once('!').ok_or_else(|| anyhow!("liker {liker:?} is not did!rkey"))?;
+            let key = format.liker(did, rkey);
+            if unliked(&key)? {
+                unlikes.insert(key);
+            }
+        }
+    }
+    Ok(unlikes)
+}
+
+/// One subject's has-liked questions, with the answers the samples expect
+struct Probes {
+    uri: String,
+    likes: usize,
+    dids: Vec<(String, bool)>,
+}
+
+/// Ask whether dids liked every sampled subject, timing each answer by the
+/// subject's like count, separately for hits and misses.
+///
+/// each subject gets `--probes` questions: `--hit-rate` of them for its own
+/// likers, the rest for sampled dids that never liked it. a liker whose likes
+/// were all unliked is expected to be a miss.
+///
+/// `has_liked` gets the subject uri and a did, and `unlikes` comes from
+/// [`sampled_unlikes`].
+pub fn run_has_liked(
+    subjects_path: &str,
+    format: Format,
+    phases: &Phases,
+    mut has_liked: impl FnMut(&str, &str) -> Result<bool>,
+    unlikes: &HashSet<Vec<u8>>,
+) -> Result<()> {
+    let probes: usize = cli::opt("probes")?.unwrap_or(DEFAULT_PROBES);
+    let hit_rate: f64 = cli::opt("hit-rate")?.unwrap_or(DEFAULT_HIT_RATE);
+    if !(0.0..=1.0).contains(&hit_rate) {
+        return Err(anyhow!("hit rate must be between 0 and 1, found {hit_rate}"))
+    }
+    println!("probes: {probes}, hit rate: {hit_rate}");
+
+    let mut subjects = vec![];
+    for line in io::BufReader::new(File::open(subjects_path)?).lines() {
+        let line = line?;
+        let Some((uri, likers)) = line.split_once('|') else {
+            return Err(anyhow!("failed to split input"))
+        };
+        let likers = likers
+            .split(';')
+            .map(|l| l.split_once('!').map(|(did, rkey)| (did.to_string(), rkey.to_string())))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("sampled likers for {uri} aren't all did!rkey"))?;
+        subjects.push((uri.to_string(), likers));
+    }
+    let mut seen = HashSet::new();
+    let pool: Vec<&str> = subjects
+        .iter()
+        .flat_map(|(_, likers)| likers.iter().map(|(did, _)| &did[..]))
+        .filter(|did| seen.insert(*did))
+        .collect();
+
+    let n_hits = (probes as f64 * hit_rate).round() as usize;
+    let mut questions = vec![];
+    for (i, (uri, likers)) in subjects.iter().enumerate() {
+        let mut own = HashSet::new();
+        let liker_dids: Vec<&str> = likers.iter().map(|(did, _)| &did[..]).filter(|did| own.insert(*did)).collect();
+        let mut dids = vec![];
+        for did in liker_dids.iter().cycle().take(n_hits) {
+            let mut live = false;
+            for (_, rkey) in likers.iter().filter(|(d, _)| d == did) {
+                live |= !unlikes.contains(&format.liker(did, rkey));
+            }
+            dids.push((did.to_string(), live));
+        }
+        let strangers = pool
+            .iter()
+            .cycle()
+            .skip(i * 31 % pool.len().max(1))
+            .take(pool.len())
+            .filter(|did| !own.contains(*did))
+            .take(probes - n_hits);
+        dids.extend(strangers.map(|did| (did.to_string(), false)));
+        questions.push(Probes { uri: uri.clone(), likes: likers.len(), dids });
+    }
+
+    let mut export = Export::from_args(Mode::HasLiked)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let mut mismatches = Mismatches::default();
+        let mut total = Duration::from_secs(0);
+        let mut hit_times = Timings::default();
+        let mut miss_times = Timings::default();
+
+        for Probes { uri, likes, dids } in &questions {
+            for (did, expected) in dids {
+                let t0 = Instant::now();
+                let found = has_liked(uri, did)?;
+                let d = t0.elapsed();
+
+                total += d;
+                let times = if *expected { &mut hit_times } else { &mut miss_times };
+                times.record(*likes, d);
+
+                let wrong = (found != *expected).then(|| (Kind::SetDiffers, format!("found {found}, expected {expected}")));
+                mismatches.record(&format!("{did} on {uri}"), wrong);
+            }
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("hits", hit_times), ("misses", miss_times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
+
+/// Page through the likes of every did in the samples with `--order` and
+/// `--limit`, timing each page by its depth. each did's listing has to be in
+/// rkey order, hold every sampled like of theirs that wasn't unliked (with its
+/// subject), and none of the sampled ones that were.
+///
+/// `list` gets the did, and returns one page from the cursor on. `unlikes`
+/// comes from [`sampled_unlikes`].
+pub fn run_forward(
+    subjects_path: &str,
+    format: Format,
+    phases: &Phases,
+    mut list: impl FnMut(&str, Order, usize, Option<&Cursor>) -> Result<forward::Page>,
+    unlikes: &HashSet<Vec<u8>>,
+) -> Result<()> {
+    if !format.forward_index {
+        return Err(anyhow!("forward mode needs a db ingested with --forward-index (and --forward-index here too)"))
+    }
+    let order: Order = cli::opt_or_default("order")?;
+    let limit: usize = cli::opt("limit")?.unwrap_or(DEFAULT_LIST_LIMIT);
+    println!("order: {order}, limit: {limit}");
+
+    // every did in the samples, with their live sampled likes
+    let mut dids: Vec<String> = vec![];
+    let mut sampled: HashMap<String, Vec<forward::Like>> = HashMap::new();
+    for line in io::BufReader::new(File::open(subjects_path)?).lines() {
+        let line = line?;
+        let Some((uri, likers)) = line.split_once('|') else {
+            return Err(anyhow!("failed to split input"))
+        };
+        for liker in likers.split(';') {
+            let (did, rkey) = liker.split_once('!').ok_or_else(|| anyhow!("liker {liker:?} is not did!rkey"))?;
+            let likes = sampled.entry(did.to_string()).or_insert_with(|| {
+                dids.push(did.to_string());
+                vec![]
+            });
+            if !unlikes.contains(&format.liker(did, rkey)) {
+                likes.push(forward::Like { rkey: rkey.to_string(), uri: uri.to_string() });
+            }
+        }
+    }
+
+    let mut export = Export::from_args(Mode::Forward)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let mut mismatches = Mismatches::default();
+        let mut total = Duration::from_secs(0);
+        let mut times = Timings::default();
+
+        for did in &dids {
+            let mut listed = vec![];
+            let mut cursor = None;
+            for depth in 0.. {
+                let t0 = Instant::now();
+                let page = list(did, order, limit, cursor.as_ref())?;
+                let d = t0.elapsed();
+
+                total += d;
+                times.record(depth, d);
+
+                listed.extend(page.likes);
+                let Some(next) = page.cursor else {
+                    break
+                };
+                cursor = Some(next);
+            }
+
+            let mut sorted = listed.clone();
+            sorted.sort_by_key(|like| format.liker(did, &like.rkey));
+            if order == Order::NewestFirst {
+                sorted.reverse();
+            }
+            let mut wrong = match sampled[did].iter().find(|like| !listed.contains(like)) {
+                Some(like) if listed.is_empty() => Some((Kind::Missing, format!("expected {like:?}"))),
+                Some(like) => Some((Kind::SetDiffers, format!("missing {like:?}"))),
+                None => None,
+            };
+            if wrong.is_none() {
+                if let Some(like) = listed.iter().find(|like| unlikes.contains(&format.liker(did, &like.rkey))) {
+                    wrong = Some((Kind::SetDiffers, format!("still has unliked {like:?}")));
+                }
+            }
+            if wrong.is_none() && listed != sorted {
+                wrong = Some((Kind::OrderDiffers, format!("not in {order} order")));
+            }
+            mismatches.record(did, wrong);
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
+
+/// Fetch every sampled subject's whole liker list `--batch-size` subjects at a
+/// time, in sample order. each batch is timed by how many keys it had, and
+/// each key by its like count, with an even share of its batch's time. run
+/// with `--batch-size 1` for the one-get-per-subject baseline.
+///
+/// `get_batch` gets the batch's subject uris, and returns each one's encoded
+/// liker list in the same order, or `None` if it's missing. the lists are
+/// checked against the samples ignoring order, since unpaged fjall likes come
+/// back sorted by liker.
+pub fn run_batch(
+    subjects_path: &str,
+    format: Format,
+    phases: &Phases,
+    mut get_batch: impl FnMut(&[&str]) -> Result<Vec<Option<Vec<u8>>>>,
+) -> Result<()> {
+    let batch_size: usize = cli::opt("batch-size")?.unwrap_or(DEFAULT_BATCH_SIZE);
+    if batch_size == 0 {
+        return Err(anyhow!("batch size must be at least 1"))
+    }
+    println!("batch size: {batch_size}");
+
+    let mut subjects = vec![];
+    for line in io::BufReader::new(File::open(subjects_path)?).lines() {
+        let line = line?;
+        let Some((uri, likers)) = line.split_once('|') else {
+            return Err(anyhow!("failed to split input"))
+        };
+        subjects.push((uri.to_string(), likers.to_string()));
+    }
+
+    let mut export = Export::from_args(Mode::Batch)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let mut mismatches = Mismatches::default();
+        let mut total = Duration::from_secs(0);
+        let mut batch_times = Timings::default();
+        let mut key_times = Timings::default();
+
+        for batch in subjects.chunks(batch_size) {
+            let uris: Vec<_> = batch.iter().map(|(uri, _)| &uri[..]).collect();
+
+            let t0 = Instant::now();
+            let found = get_batch(&uris)?;
+            let d = t0.elapsed();
+
+            total += d;
+            batch_times.record(batch.len(), d);
+            let share = d.as_nanos() as u64 / batch.len() as u64;
+
+            if found.len() != batch.len() {
+                return Err(anyhow!("a batch of {} came back with {} results", batch.len(), found.len()))
+            }
+            for ((uri, likers), found) in batch.iter().zip(found) {
+                let mut expected: Vec<_> = likers.split(';').collect();
+                key_times.record_nanos(expected.len(), share);
+
+                let found = found.map(|found| format.encoding.likers_text(&found)).transpose()?.unwrap_or_default();
+                let mut found: Vec<_> = found.split(';').filter(|l| !l.is_empty()).collect();
+                expected.sort_unstable();
+                found.sort_unstable();
+                mismatches.record(uri, verify::classify(&found, &expected));
+            }
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("batches", batch_times), ("keys", key_times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
+
+/// Stream every like on the records of each author in the samples, only on
+/// `--collection` if it's given, timing each author by how many likes came
+/// back. every sampled like on their subjects has to be there, and nothing on
+/// anyone else's records. run with each `--layout` that can scan by authority
+/// to compare them.
+///
+/// `likes_for` gets the did and the collection, and returns the author's likes.
+pub fn run_authority(
+    subjects_path: &str,
+    format: Format,
+    phases: &Phases,
+    mut likes_for: impl FnMut(&str, Option<&str>) -> Result<Vec<authority::Like>>,
+) -> Result<()> {
+    let collection: Option<String> = cli::opt("collection")?;
+    println!("collection: {}", collection.as_deref().unwrap_or("all"));
+
+    // every author in the samples, with the sampled likes on their records
+    let mut dids: Vec<String> = vec![];
+    let mut sampled: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for line in io::BufReader::new(File::open(subjects_path)?).lines() {
+        let line = line?;
+        let Some((uri, likers)) = line.split_once('|') else {
+            return Err(anyhow!("failed to split input"))
+        };
+        let Some((did, found, _)) = split_record(uri) else {
+            return Err(anyhow!("sampled subject {uri:?} isn't a record"))
+        };
+        if collection.as_deref().is_some_and(|c| c != found) {
+            continue
+        }
+        let likes = sampled.entry(did.to_string()).or_insert_with(|| {
+            dids.push(did.to_string());
+            vec![]
+        });
+        likes.extend(likers.split(';').map(|liker| (uri.to_string(), liker.to_string())));
+    }
+    println!("authorities: {}", dids.len());
+
+    let mut export = Export::from_args(Mode::Authority)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let mut mismatches = Mismatches::default();
+        let mut total = Duration::from_secs(0);
+        let mut times = Timings::default();
+
+        for did in &dids {
+            let t0 = Instant::now();
+            let found = likes_for(did, collection.as_deref())?;
+            let d = t0.elapsed();
+
+            total += d;
+            times.record(found.len(), d);
+
+            let mut wrong = None;
+            let mut seen = HashSet::new();
+            for like in found {
+                let theirs = split_record(&like.uri)
+                    .is_some_and(|(d, c, _)| d == did && collection.as_deref().is_none_or(|want| want == c));
+                if !theirs && wrong.is_none() {
+                    wrong = Some((Kind::SetDiffers, format!("has one on {}", like.uri)));
+                }
+                seen.insert((like.uri, format.encoding.likers_text(&like.liker)?));
+            }
+            if let Some((uri, liker)) = sampled[did].iter().find(|like| !seen.contains(*like)) {
+                let kind = if seen.is_empty() { Kind::Missing } else { Kind::SetDiffers };
+                wrong = wrong.or(Some((kind, format!("missing {liker} on {uri}"))));
+            }
+            mismatches.record(did, wrong);
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
@@ -0,0 +1,383 @@
+//! offline bulk load: sort the whole input, then write each key once
+//!
+//! likes are sorted by subject key (keeping input order within a subject) and
+//! unlikes by liker, in memory up to `--sort-mb` (default 256) between them
+//! and spilled to sorted runs under `--sort-dir` past that, then merged. each
+//! subject's likers become its final values in one go, which are sorted again
+//! (pages and like keys don't sort the same as subject keys) so the store gets
+//! every key once, in order. only the liker lists and unlikes are built, not the extra indexes.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use tinyjson::JsonValue;
+
+use crate::cli;
+use crate::format::Format;
+use crate::paging::{self, Header};
+
+pub const DEFAULT_SORT_MB: usize = 256;
+
+/// bytes counted for each buffered record on top of its key and value
+const RECORD_OVERHEAD: usize = 64;
+
+/// A like keyed by its subject, or an unlike
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Like { key: Vec<u8>, liker: Vec<u8> },
+    Unlike { liker: Vec<u8> },
+}
+
+impl Entry {
+    /// Parse an input line and make its keys
+    pub fn parse(format: Format, s: &str) -> Result<Self> {
+        let parsed: JsonValue = s.parse()?;
+        let entry = <Vec<_>>::try_from(parsed)?;
+        if entry.len() != 4 {
+            return Err(anyhow!("expected entries of length 4"))
+        }
+        let action = String::try_from(entry[0].clone())?;
+        let did = String::try_from(entry[1].clone())?;
+        let rkey = String::try_from(entry[2].clone())?;
+        match action.as_str() {
+            "c" => {
+                let uri = String::try_from(entry[3].clone())?;
+                Ok(Entry::Like { key: format.subject_key(&uri), liker: format.liker(&did, &rkey) })
+            }
+            "d" => Ok(Entry::Unlike { liker: format.liker(&did, &rkey) }),
+            _ => Err(anyhow!("need 'c' or 'd' for entry action type")),
+        }
+    }
+}
+
+/// Refuse formats with indexes that bulk loading doesn't build
+pub fn check(format: Format) -> Result<()> {
+    if format.listing || format.counts || format.liked || format.forward_index {
+        return Err(anyhow!("bulk load only builds liker lists and unlikes, not --listing, --counts, --liked or --forward-index"))
+    }
+    Ok(())
+}
+
+/// Where and how much to sort in memory
+#[derive(Debug, Clone)]
+pub struct Sort {
+    pub dir: PathBuf,
+    /// bytes buffered by every sort at once before runs are spilled
+    pub limit: usize,
+}
+
+impl Sort {
+    /// `--sort-dir` (default `default_dir`) and `--sort-mb`
+    pub fn from_args(default_dir: &str) -> Result<Self> {
+        Ok(Sort {
+            dir: cli::opt("sort-dir")?.unwrap_or_else(|| default_dir.into()),
+            limit: cli::opt("sort-mb")?.unwrap_or(DEFAULT_SORT_MB) * 2_usize.pow(20),
+        })
+    }
+
+    /// A sorter with half the limit: two buffer at once, likes and unlikes
+    /// while reading the input, and then unlikes and the final records
+    fn sorter(&self, name: &'static str) -> Sorter {
+        let sort = Sort { dir: self.dir.clone(), limit: self.limit / 2 };
+        Sorter { sort, name, buffer: vec![], buffered: 0, runs: vec![] }
+    }
+}
+
+/// External merge sort of key/value records, stable for equal keys
+struct Sorter {
+    sort: Sort,
+    name: &'static str,
+    buffer: Vec<(Vec<u8>, Vec<u8>)>,
+    buffered: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl Sorter {
+    fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.buffered += key.len() + value.len() + RECORD_OVERHEAD;
+        self.buffer.push((key, value));
+        if self.buffered >= self.sort.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.sort.dir)?;
+        let path = self.sort.dir.join(format!("{}-{}.run", self.name, self.runs.len()));
+        let mut out = BufWriter::new(File::create(&path)?);
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, value) in self.buffer.drain(..) {
+            for bytes in [&key, &value] {
+                out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                out.write_all(bytes)?;
+            }
+        }
+        out.flush()?;
+        self.runs.push(path);
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Every record by key, equal keys in the order they were pushed, and how
+    /// many runs were spilled
+    fn finish(mut self) -> Result<(Merged, usize)> {
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut sources = vec![];
+        for path in &self.runs {
+            sources.push(Source::Run(BufReader::new(File::open(path)?)));
+        }
+        // the buffer holds the newest records, so it goes last
+        sources.push(Source::Memory(std::mem::take(&mut self.buffer).into_iter()));
+        let spilled = self.runs.len();
+        let mut merged = Merged { sources, heads: BinaryHeap::new(), runs: self.runs };
+        for i in 0..merged.sources.len() {
+            merged.advance(i)?;
+        }
+        Ok((merged, spilled))
+    }
+}
+
+enum Source {
+    Run(BufReader<File>),
+    Memory(std::vec::IntoIter<(Vec<u8>, Vec<u8>)>),
+}
+
+impl Source {
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let run = match self {
+            Source::Memory(records) => return Ok(records.next()),
+            Source::Run(run) => run,
+        };
+        let mut len = [0; 4];
+        match run.read_exact(&mut len) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            read => read?,
+        }
+        let mut key = vec![0; u32::from_le_bytes(len) as usize];
+        run.read_exact(&mut key)?;
+        run.read_exact(&mut len)?;
+        let mut value = vec![0; u32::from_le_bytes(len) as usize];
+        run.read_exact(&mut value)?;
+        Ok(Some((key, value)))
+    }
+}
+
+/// a source's next record, as (key, source, value)
+type Head = Reverse<(Vec<u8>, usize, Vec<u8>)>;
+
+/// The sources' records merged by key, ties going to the earlier source
+struct Merged {
+    sources: Vec<Source>,
+    heads: BinaryHeap<Head>,
+    runs: Vec<PathBuf>,
+}
+
+impl Merged {
+    fn advance(&mut self, source: usize) -> Result<()> {
+        if let Some((key, value)) = self.sources[source].next()? {
+            self.heads.push(Reverse((key, source, value)));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Merged {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((key, source, value)) = self.heads.pop()?;
+        if let Err(e) = self.advance(source) {
+            return Some(Err(e))
+        }
+        Some(Ok((key, value)))
+    }
+}
+
+impl Drop for Merged {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+    }
+}
+
+/// What goes to the store, all the liker values by key and then all the
+/// unlikes by key, each key once
+#[derive(Debug, PartialEq, Eq)]
+pub enum Record<'a> {
+    Likers { key: &'a [u8], value: &'a [u8] },
+    Unlike(&'a [u8]),
+}
+
+/// Counts from a bulk load
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Loaded {
+    pub likes: u64,
+    pub unlikes: u64,
+    pub subjects: u64,
+    /// sorted runs spilled to disk, over every sort
+    pub runs: usize,
+}
+
+/// Sort `entries`, turn each subject's likers (in input order) into its keys
+/// and values with `values`, and `write` them and then the unlikes in order
+pub fn load(
+    entries: impl IntoIterator<Item = Result<Entry>>,
+    sort: &Sort,
+    mut values: impl FnMut(&[u8], &[Vec<u8>]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>,
+    mut write: impl FnMut(Record) -> Result<()>,
+) -> Result<Loaded> {
+    let mut loaded = Loaded::default();
+    let (mut likes, mut unlikes) = (sort.sorter("likes"), sort.sorter("unlikes"));
+    for entry in entries {
+        match entry? {
+            Entry::Like { key, liker } => {
+                likes.push(key, liker)?;
+                loaded.likes += 1;
+            }
+            Entry::Unlike { liker } => {
+                unlikes.push(liker, vec![])?;
+                loaded.unlikes += 1;
+            }
+        }
+    }
+
+    let mut records = sort.sorter("records");
+    let (likes, runs) = likes.finish()?;
+    loaded.runs += runs;
+    let mut subject: Option<(Vec<u8>, Vec<Vec<u8>>)> = None;
+    let mut finish_subject = |subject: Option<(Vec<u8>, Vec<Vec<u8>>)>, loaded: &mut Loaded| -> Result<()> {
+        if let Some((key, likers)) = subject {
+            for (key, value) in values(&key, &likers)? {
+                records.push(key, value)?;
+            }
+            loaded.subjects += 1;
+        }
+        Ok(())
+    };
+    for like in likes {
+        let (key, liker) = like?;
+        match &mut subject {
+            Some((current, likers)) if *current == key => likers.push(liker),
+            _ => finish_subject(subject.replace((key, vec![liker])), &mut loaded)?,
+        }
+    }
+    finish_subject(subject, &mut loaded)?;
+
+    let (records, runs) = records.finish()?;
+    loaded.runs += runs;
+    // a later value for the same key wins, like a later put would
+    let mut pending: Option<(Vec<u8>, Vec<u8>)> = None;
+    for record in records {
+        let (key, value) = record?;
+        if let Some((last_key, last_value)) = pending.replace((key, value)) {
+            if pending.as_ref().is_some_and(|(key, _)| *key != last_key) {
+                write(Record::Likers { key: &last_key, value: &last_value })?;
+            }
+        }
+    }
+    if let Some((key, value)) = pending {
+        write(Record::Likers { key: &key, value: &value })?;
+    }
+
+    let (unlikes, runs) = unlikes.finish()?;
+    loaded.runs += runs;
+    let mut last: Option<Vec<u8>> = None;
+    for unlike in unlikes {
+        let (liker, _) = unlike?;
+        if last.as_ref() != Some(&liker) {
+            write(Record::Unlike(&liker))?;
+            last = Some(liker);
+        }
+    }
+    let _ = fs::remove_dir(&sort.dir);
+    Ok(loaded)
+}
+
+/// A subject's likers as online ingest leaves them: a header and full pages
+/// when paged, otherwise one list at the subject key. `value` gets each list
+/// before it's stored, for compression.
+pub fn liker_values(
+    format: Format,
+    key: &[u8],
+    likers: &[Vec<u8>],
+    mut value: impl FnMut(Vec<u8>) -> Result<Vec<u8>>,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let join = |likers: &[Vec<u8>]| format.encoding.join(None, likers.iter().map(|l| &l[..]));
+    let Some(paging) = format.paging else {
+        return Ok(vec![(key.to_vec(), value(join(likers))?)])
+    };
+    let pages = likers.chunks(paging.page_size as usize);
+    let header = Header { count: likers.len() as u64, pages: pages.len() as u32 };
+    let mut values = vec![(key.to_vec(), header.to_bytes().to_vec())];
+    for (page, likers) in pages.enumerate() {
+        values.push((paging::page_key(key, page as u32), value(join(likers))?));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::Paging;
+
+    fn sort(name: &str, limit: usize) -> Sort {
+        Sort { dir: std::env::temp_dir().join(format!("kv-for-likes-bulk-{name}-{}", std::process::id())), limit }
+    }
+
+    #[test]
+    fn test_sorter_spills_and_stays_stable() {
+        let sort = sort("sorter", 200);
+        let mut sorter = sort.sorter("test");
+        for i in 0..100u32 {
+            sorter.push(vec![(i % 7) as u8], i.to_be_bytes().to_vec()).unwrap();
+        }
+        let (merged, spilled) = sorter.finish().unwrap();
+        assert!(spilled > 1);
+        let records: Vec<_> = merged.map(Result::unwrap).collect();
+        let mut expected: Vec<_> = (0..100u32).map(|i| (vec![(i % 7) as u8], i.to_be_bytes().to_vec())).collect();
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(records, expected);
+        assert_eq!(fs::read_dir(&sort.dir).unwrap().count(), 0);
+        fs::remove_dir(&sort.dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_builds_final_values() {
+        let format = Format { paging: Some(Paging { page_size: 2 }), ..Format::default() };
+        let like = |uri: &str, rkey: &str| Ok(Entry::Like { key: format.subject_key(uri), liker: format.liker("did:web:a.com", rkey) });
+        let entries = vec![
+            like("at://did:web:b.com/app.bsky.feed.post/2", "3"),
+            like("at://did:web:b.com/app.bsky.feed.post/1", "1"),
+            Ok(Entry::Unlike { liker: format.liker("did:web:a.com", "1") }),
+            like("at://did:web:b.com/app.bsky.feed.post/1", "2"),
+            like("at://did:web:b.com/app.bsky.feed.post/1", "4"),
+            Ok(Entry::Unlike { liker: format.liker("did:web:a.com", "1") }),
+        ];
+        let mut written = vec![];
+        let loaded = load(entries, &sort("load", 1 << 20), |key, likers| liker_values(format, key, likers, Ok), |record| {
+            written.push(match record {
+                Record::Likers { key, value } => (key.to_vec(), Some(value.to_vec())),
+                Record::Unlike(key) => (key.to_vec(), None),
+            });
+            Ok(())
+        }).unwrap();
+        assert_eq!(loaded, Loaded { likes: 4, unlikes: 2, subjects: 2, runs: 0 });
+
+        let first = format.subject_key("at://did:web:b.com/app.bsky.feed.post/1");
+        let liker = |rkey| format.liker("did:web:a.com", rkey);
+        let expected_first = [
+            (first.clone(), Some(Header { count: 3, pages: 2 }.to_bytes().to_vec())),
+            (paging::page_key(&first, 0), Some(format.encoding.join(None, [&liker("1")[..], &liker("2")[..]]))),
+            (paging::page_key(&first, 1), Some(liker("4"))),
+        ];
+        assert_eq!(written[..3], expected_first);
+        assert_eq!(written[3].0, format.subject_key("at://did:web:b.com/app.bsky.feed.post/2"));
+        assert_eq!(written[5..], [(liker("1"), None)]);
+        assert!(check(format).is_ok());
+        assert!(check(Format { counts: true, ..format }).is_err());
+    }
+}
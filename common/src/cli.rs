@@ -0,0 +1,40 @@
+//! minimal `--name value` option lookup for the benchmark binaries
+use std::fmt::Display;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+
+/// Find `--name value` or `--name=value` in the process args and parse it
+pub fn opt<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let flag = format!("--{name}");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let raw = if arg == flag {
+            args.next().ok_or_else(|| anyhow!("{flag} needs a value"))?
+        } else if let Some(v) = arg.strip_prefix(&flag).and_then(|r| r.strip_prefix('=')) {
+            v.to_string()
+        } else {
+            continue
+        };
+        return raw.parse().map(Some).map_err(|e| anyhow!("bad value for {flag}: {e}"))
+    }
+    Ok(None)
+}
+
+/// Like `opt`, falling back to the default when not given
+pub fn opt_or_default<T>(name: &str) -> Result<T>
+where
+    T: FromStr + Default,
+    T::Err: Display,
+{
+    Ok(opt(name)?.unwrap_or_default())
+}
+
+/// Whether a bare `--name` flag was passed
+pub fn flag(name: &str) -> bool {
+    let flag = format!("--{name}");
+    std::env::args().skip(1).any(|arg| arg == flag)
+}
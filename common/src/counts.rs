@@ -0,0 +1,75 @@
+//! like counts maintained next to the liker lists, so showing a number doesn't
+//! mean reading (or scanning) the whole list
+//!
+//! with `--counts`, every create adds one to its subject's count and every
+//! delete takes one away, in the same write as the like itself. deletes only
+//! carry the like's did and rkey, so each create also records its subject key
+//! under the like's liker key.
+//!
+//! counts are stored as 8-byte little-endian signed deltas, so rocks can sum
+//! them in a merge operator without reading first.
+use anyhow::{anyhow, Result};
+
+pub const COUNT_LEN: usize = 8;
+
+pub fn to_bytes(count: i64) -> [u8; COUNT_LEN] {
+    count.to_le_bytes()
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<i64> {
+    let Ok(bytes) = bytes.try_into() else {
+        return Err(anyhow!("count must be {COUNT_LEN} bytes, found {}", bytes.len()))
+    };
+    Ok(i64::from_le_bytes(bytes))
+}
+
+/// Add up a stored count (if any) and some deltas
+pub fn sum<'a>(existing: Option<&[u8]>, deltas: impl IntoIterator<Item = &'a [u8]>) -> Result<i64> {
+    let mut count = existing.map(from_bytes).transpose()?.unwrap_or(0);
+    for delta in deltas {
+        count += from_bytes(delta)?;
+    }
+    Ok(count)
+}
+
+/// How many of a sampled subject's `;`-separated likers haven't been unliked
+pub fn live(likers: &str, mut unliked: impl FnMut(&str, &str) -> Result<bool>) -> Result<i64> {
+    let mut count = 0;
+    for liker in likers.split(';') {
+        let (did, rkey) = liker.split_once('!').ok_or_else(|| anyhow!("liker {liker:?} is not did!rkey"))?;
+        if !unliked(did, rkey)? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for n in [0, 1, -1, 12345, i64::MAX] {
+            assert_eq!(from_bytes(&to_bytes(n)).unwrap(), n);
+        }
+        assert!(from_bytes(b"short").is_err());
+    }
+
+    #[test]
+    fn test_sum() {
+        let up = to_bytes(1);
+        let down = to_bytes(-1);
+        assert_eq!(sum(None, [&up[..], &up[..]]).unwrap(), 2);
+        assert_eq!(sum(Some(&to_bytes(5)), [&up[..], &down[..], &down[..]]).unwrap(), 4);
+        assert_eq!(sum(None, []).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_live() {
+        let likers = "did:plc:a!3ld5aaaaaaaaa;did:plc:b!3ld5aaaaaaaab;did:web:c.com!self";
+        assert_eq!(live(likers, |_, _| Ok(false)).unwrap(), 3);
+        assert_eq!(live(likers, |did, _| Ok(did == "did:plc:b")).unwrap(), 2);
+        assert!(live("no-separator", |_, _| Ok(false)).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! zstd dictionary compression for liker values
+//!
+//! liker lists are short and full of the same `did:plc:` and tid prefixes, so
+//! a dictionary trained on a sample of them compresses each value on its own
+//! far better than plain zstd can. rocks trains its own per-file dictionaries
+//! (see its `zstd-dict` profile); this codec is for backends without any
+//! native compression.
+//!
+//! only liker values go through the codec: page headers are stored as-is.
+use std::fmt;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use zstd::bulk::{Compressor, Decompressor};
+
+use crate::cli;
+
+pub const DEFAULT_DICT_SIZE: usize = 32 * 1024;
+pub const DEFAULT_LEVEL: i32 = 3;
+pub const DEFAULT_SAMPLES: usize = 10_000;
+pub const DEFAULT_DICT_PATH: &str = "./likes.dict";
+
+/// Train a dictionary of up to `max_size` bytes from sample values
+pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Vec<u8>> {
+    if samples.is_empty() {
+        return Err(anyhow!("no values to train a dictionary from"))
+    }
+    Ok(zstd::dict::from_samples(samples, max_size)?)
+}
+
+/// How often to take a sample to get about `want` of them from `total` values
+pub fn sample_stride(total: u64, want: usize) -> u64 {
+    (total / want.max(1) as u64).max(1)
+}
+
+pub struct ValueCodec {
+    compressor: Compressor<'static>,
+    decompressor: Decompressor<'static>,
+    dict_len: usize,
+    level: i32,
+}
+
+impl ValueCodec {
+    pub fn new(dict: &[u8], level: i32) -> Result<Self> {
+        Ok(ValueCodec {
+            compressor: Compressor::with_dictionary(level, dict)?,
+            decompressor: Decompressor::with_dictionary(dict)?,
+            dict_len: dict.len(),
+            level,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>, level: i32) -> Result<Self> {
+        Self::new(&std::fs::read(path)?, level)
+    }
+
+    /// The codec from `--dict path` (and optionally `--dict-level`), if given
+    pub fn from_args() -> Result<Option<Self>> {
+        let Some(path) = cli::opt::<String>("dict")? else {
+            return Ok(None)
+        };
+        let level = cli::opt("dict-level")?.unwrap_or(DEFAULT_LEVEL);
+        Ok(Some(Self::load(path, level)?))
+    }
+
+    pub fn compress(&mut self, value: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.compressor.compress(value)?)
+    }
+
+    pub fn decompress(&mut self, compressed: &[u8]) -> Result<Vec<u8>> {
+        let Some(len) = zstd::zstd_safe::get_frame_content_size(compressed)
+            .map_err(|_| anyhow!("value is not a zstd frame"))? else {
+            return Err(anyhow!("compressed value is missing its content size"))
+        };
+        Ok(self.decompressor.decompress(compressed, len as usize)?)
+    }
+}
+
+impl fmt::Display for ValueCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "zstd level {} with a {} byte dictionary", self.level, self.dict_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Vec<u8>> {
+        (0..2000u64)
+            .map(|i| {
+                (0..1 + i % 7)
+                    .map(|j| format!("did:plc:{:0>24}!3ld5{:0>9}", (i * 31 + j) % 997, i * 13 + j))
+                    .collect::<Vec<_>>()
+                    .join(";")
+                    .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_with_trained_dict() {
+        let samples = samples();
+        let dict = train(&samples, 4 * 1024).unwrap();
+        assert!(!dict.is_empty() && dict.len() <= 4 * 1024);
+        let mut codec = ValueCodec::new(&dict, DEFAULT_LEVEL).unwrap();
+        for value in samples.iter().step_by(97) {
+            let compressed = codec.compress(value).unwrap();
+            assert_eq!(&codec.decompress(&compressed).unwrap(), value);
+        }
+        let value = &samples[500];
+        assert!(codec.compress(value).unwrap().len() < zstd::bulk::compress(value, DEFAULT_LEVEL).unwrap().len());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        let dict = train(&samples(), 4 * 1024).unwrap();
+        let mut codec = ValueCodec::new(&dict, DEFAULT_LEVEL).unwrap();
+        assert!(codec.decompress(b"not zstd").is_err());
+        assert!(train::<Vec<u8>>(&[], 1024).is_err());
+    }
+
+    #[test]
+    fn test_sample_stride() {
+        assert_eq!(sample_stride(100, 10_000), 1);
+        assert_eq!(sample_stride(1_000_000, 10_000), 100);
+        assert_eq!(sample_stride(5, 0), 5);
+    }
+}
@@ -0,0 +1,137 @@
+//! compact binary codec for DIDs
+//!
+//! `did:plc:` plus 24 base32 chars packs into 15 bytes after a tag byte. any
+//! other DID (`did:web` and friends) is kept verbatim after its own tag and
+//! terminated with `0x00`, so every encoded DID is self-delimiting.
+//!
+//! packed plc identifiers use the base32 chars in ascii order, so encoded
+//! DIDs of the same kind sort the same as their strings.
+use anyhow::{anyhow, Result};
+
+const PLC_PREFIX: &str = "did:plc:";
+const PLC_ID_LEN: usize = 24;
+pub const PLC_PACKED_LEN: usize = 15;
+
+// base32 (rfc 4648, lowercase) reordered to ascii order
+const ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+const PLC_TAG: u8 = 0x01;
+const VERBATIM_TAG: u8 = 0x02;
+const VERBATIM_END: u8 = 0x00;
+
+fn char_value(c: u8) -> Option<u128> {
+    match c {
+        b'2'..=b'7' => Some((c - b'2') as u128),
+        b'a'..=b'z' => Some((c - b'a') as u128 + 6),
+        _ => None,
+    }
+}
+
+/// Parse the identifier of a `did:plc` to its 120-bit value, if it is one
+fn parse_plc(did: &str) -> Option<u128> {
+    let id = did.strip_prefix(PLC_PREFIX)?;
+    if id.len() != PLC_ID_LEN {
+        return None
+    }
+    id.bytes().try_fold(0u128, |n, c| Some((n << 5) | char_value(c)?))
+}
+
+fn format_plc(mut n: u128) -> String {
+    let mut id = [0u8; PLC_ID_LEN];
+    for c in id.iter_mut().rev() {
+        *c = ALPHABET[(n & 0x1f) as usize];
+        n >>= 5;
+    }
+    format!("{PLC_PREFIX}{}", std::str::from_utf8(&id).expect("alphabet is ascii"))
+}
+
+/// Append the encoded form of a DID
+pub fn encode_into(did: &str, out: &mut Vec<u8>) {
+    match parse_plc(did) {
+        Some(n) => {
+            out.push(PLC_TAG);
+            out.extend_from_slice(&n.to_be_bytes()[16 - PLC_PACKED_LEN..]);
+        }
+        None => {
+            out.push(VERBATIM_TAG);
+            out.extend_from_slice(did.as_bytes());
+            out.push(VERBATIM_END);
+        }
+    }
+}
+
+pub fn encode(did: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + PLC_PACKED_LEN);
+    encode_into(did, &mut out);
+    out
+}
+
+/// Decode one DID from the start of `bytes`, returning it with the number of
+/// bytes consumed
+pub fn decode(bytes: &[u8]) -> Result<(String, usize)> {
+    match bytes.first() {
+        Some(&PLC_TAG) => {
+            let Some(packed) = bytes.get(1..1 + PLC_PACKED_LEN) else {
+                return Err(anyhow!("packed did:plc needs {PLC_PACKED_LEN} bytes after its tag"))
+            };
+            let mut be = [0u8; 16];
+            be[16 - PLC_PACKED_LEN..].copy_from_slice(packed);
+            Ok((format_plc(u128::from_be_bytes(be)), 1 + PLC_PACKED_LEN))
+        }
+        Some(&VERBATIM_TAG) => {
+            let Some(end) = bytes.iter().position(|b| *b == VERBATIM_END) else {
+                return Err(anyhow!("verbatim did is missing its terminator"))
+            };
+            Ok((std::str::from_utf8(&bytes[1..end])?.to_string(), end + 1))
+        }
+        Some(tag) => Err(anyhow!("unknown did tag {tag:#04x}")),
+        None => Err(anyhow!("no bytes to decode a did from")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plc_round_trip() {
+        for did in [
+            "did:plc:hdhoaan3xa3jiuq4fg4mefid",
+            "did:plc:222222222222222222222222",
+            "did:plc:zzzzzzzzzzzzzzzzzzzzzzzz",
+        ] {
+            let encoded = encode(did);
+            assert_eq!(encoded.len(), 1 + PLC_PACKED_LEN);
+            assert_eq!(decode(&encoded).unwrap(), (did.to_string(), 1 + PLC_PACKED_LEN));
+        }
+    }
+
+    #[test]
+    fn test_verbatim_round_trip() {
+        // did:web, wrong length, bad charset
+        for did in [
+            "did:web:example.com",
+            "did:plc:hdhoaan3xa3jiuq4fg4mefi",
+            "did:plc:hdhoaan3xa3jiuq4fg4mef01",
+        ] {
+            let encoded = encode(did);
+            assert_eq!(encoded.len(), did.len() + 2);
+            assert_eq!(decode(&encoded).unwrap(), (did.to_string(), did.len() + 2));
+        }
+    }
+
+    #[test]
+    fn test_plc_sort_order() {
+        let mut dids = vec![
+            "did:plc:hdhoaan3xa3jiuq4fg4mefid",
+            "did:plc:iyr4nadkkq2toocambsr3inz",
+            "did:plc:222222222222222222222223",
+            "did:plc:hdhoaan3xa3jiuq4fg4mefic",
+        ];
+        let mut encoded: Vec<_> = dids.iter().map(|d| encode(d)).collect();
+        encoded.sort();
+        dids.sort();
+        let decoded: Vec<_> = encoded.iter().map(|e| decode(e).unwrap().0).collect();
+        assert_eq!(decoded, dids);
+    }
+}
@@ -0,0 +1,136 @@
+//! one durability setting for every backend's ingest, `--durability`
+//!
+//! - `none`: nothing is synced until the end, and entries can sit in the
+//!   store's own buffers or an open transaction until then.
+//! - `buffered`: each entry is handed to the OS as it's written (so it
+//!   survives the process dying), but never synced.
+//! - `entries:N` (default `entries:100`): buffered, and every `N`th entry's
+//!   write is synced along with everything before it.
+//! - `ms:T`: buffered, and the first write once `T` ms have passed since the
+//!   last sync is synced.
+//! - `sync`: every entry's write is synced.
+//!
+//! each backend maps these to its own knobs.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+
+use crate::cli;
+
+pub const DEFAULT_SYNC_ENTRIES: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    None,
+    Buffered,
+    Entries(u64),
+    Interval(Duration),
+    Sync,
+}
+
+impl Default for Durability {
+    fn default() -> Self {
+        Durability::Entries(DEFAULT_SYNC_ENTRIES)
+    }
+}
+
+impl FromStr for Durability {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (mode, n) = match s.split_once(':') {
+            Some((mode, n)) => (mode, Some(n.parse::<u64>().map_err(|e| anyhow!("bad durability {s:?}: {e}"))?)),
+            None => (s, None),
+        };
+        match (mode, n) {
+            ("none", None) => Ok(Durability::None),
+            ("buffered", None) => Ok(Durability::Buffered),
+            ("entries", Some(0)) | ("ms", Some(0)) => Err(anyhow!("durability {s:?} needs a number above 0")),
+            ("entries", Some(n)) => Ok(Durability::Entries(n)),
+            ("ms", Some(ms)) => Ok(Durability::Interval(Duration::from_millis(ms))),
+            ("sync", None) => Ok(Durability::Sync),
+            _ => Err(anyhow!("durability must be none, buffered, entries:N, ms:T or sync, not {s:?}")),
+        }
+    }
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Durability::None => write!(f, "none"),
+            Durability::Buffered => write!(f, "buffered"),
+            Durability::Entries(n) => write!(f, "entries:{n}"),
+            Durability::Interval(interval) => write!(f, "ms:{}", interval.as_millis()),
+            Durability::Sync => write!(f, "sync"),
+        }
+    }
+}
+
+impl Durability {
+    /// `--durability`, defaulting to `entries:100`
+    pub fn from_args() -> Result<Self> {
+        cli::opt_or_default("durability")
+    }
+
+    /// A tracker for when the `entries:N` and `ms:T` syncs are due
+    pub fn syncs(&self) -> Syncs {
+        Syncs { durability: *self, entries: 0, last: Instant::now() }
+    }
+}
+
+/// Counts entries and time since the last periodic sync
+#[derive(Debug)]
+pub struct Syncs {
+    durability: Durability,
+    entries: u64,
+    last: Instant,
+}
+
+impl Syncs {
+    /// Count the entry about to be written, and say whether its write is a
+    /// periodic sync. always false for the other modes: `sync` syncs every
+    /// write, and `none` and `buffered` sync none of them.
+    pub fn due(&mut self) -> bool {
+        self.entries += 1;
+        let due = match self.durability {
+            Durability::Entries(n) => self.entries >= n,
+            Durability::Interval(interval) => self.last.elapsed() >= interval,
+            Durability::None | Durability::Buffered | Durability::Sync => false,
+        };
+        if due {
+            self.entries = 0;
+            self.last = Instant::now();
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        for s in ["none", "buffered", "entries:100", "ms:250", "sync"] {
+            assert_eq!(s.parse::<Durability>().unwrap().to_string(), s);
+        }
+        assert_eq!(Durability::default(), Durability::Entries(DEFAULT_SYNC_ENTRIES));
+        for bad in ["entries", "entries:0", "ms:soon", "none:3", "fsync"] {
+            assert!(bad.parse::<Durability>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_syncs_due() {
+        let mut syncs = Durability::Entries(3).syncs();
+        let due: Vec<_> = (0..7).map(|_| syncs.due()).collect();
+        assert_eq!(due, [false, false, true, false, false, true, false]);
+        let mut syncs = Durability::Interval(Duration::ZERO).syncs();
+        assert!(syncs.due() && syncs.due());
+        for durability in [Durability::None, Durability::Buffered, Durability::Sync] {
+            let mut syncs = durability.syncs();
+            assert!(!(0..10).any(|_| syncs.due()));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! how subjects and likers get turned into bytes for keys and values
+//!
+//! `Text` is what every backend has always written: the subject uri as the
+//! key, and `did!rkey` likers joined with `;`. `Tid` packs record keys with
+//! the [`tid`] codec. `Compact` also packs DIDs with the [`did`] codec, for
+//! the likers and for the subject authority: a middle ground between text
+//! and interning everything through an id table.
+//!
+//! liker entries of both binary encodings are self-delimiting, so lists are
+//! just concatenated.
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use crate::{did, tid};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Text,
+    Tid,
+    Compact,
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Encoding::Text),
+            "tid" => Ok(Encoding::Tid),
+            "compact" => Ok(Encoding::Compact),
+            _ => Err(anyhow!("unknown encoding {s:?}, expected 'text', 'tid', or 'compact'")),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Text => "text",
+            Encoding::Tid => "tid",
+            Encoding::Compact => "compact",
+        })
+    }
+}
+
+/// split a full `at://did/collection/rkey` uri into its parts
+pub(crate) fn split_record(uri: &str) -> Option<(&str, &str, &str)> {
+    let (did, path) = uri.strip_prefix("at://")?.split_once('/')?;
+    let (collection, rkey) = path.split_once('/')?;
+    if rkey.contains('/') {
+        return None
+    }
+    Some((did, collection, rkey))
+}
+
+impl Encoding {
+    /// The key for a liked subject
+    pub fn subject_key(&self, uri: &str) -> Vec<u8> {
+        match (self, split_record(uri)) {
+            (Encoding::Tid, Some((did, collection, rkey))) => {
+                let mut key = format!("at://{did}/{collection}/").into_bytes();
+                tid::encode_into(rkey, &mut key);
+                key
+            }
+            (Encoding::Compact, Some((did, collection, rkey))) => {
+                let mut key = did::encode(did);
+                key.extend_from_slice(collection.as_bytes());
+                key.push(b'/');
+                tid::encode_into(rkey, &mut key);
+                key
+            }
+            _ => uri.as_bytes().to_vec(),
+        }
+    }
+
+    /// One liker entry, which is also the key for an unlike
+    pub fn liker(&self, did: &str, rkey: &str) -> Vec<u8> {
+        match self {
+            Encoding::Text => format!("{did}!{rkey}").into_bytes(),
+            Encoding::Tid => {
+                let mut entry = Vec::with_capacity(did.len() + 1 + tid::PACKED_LEN);
+                entry.extend_from_slice(did.as_bytes());
+                entry.push(b'!');
+                tid::encode_into(rkey, &mut entry);
+                entry
+            }
+            Encoding::Compact => {
+                let mut entry = did::encode(did);
+                tid::encode_into(rkey, &mut entry);
+                entry
+            }
+        }
+    }
+
+    /// The bytes every liker entry from this DID starts with, and no other's
+    pub fn liker_prefix(&self, did: &str) -> Vec<u8> {
+        match self {
+            Encoding::Text | Encoding::Tid => format!("{did}!").into_bytes(),
+            Encoding::Compact => did::encode(did),
+        }
+    }
+
+    /// Decode the rkey part of a liker entry: whatever follows its [`Self::liker_prefix`]
+    pub fn decode_rkey(&self, bytes: &[u8]) -> Result<String> {
+        match self {
+            Encoding::Text => Ok(std::str::from_utf8(bytes)?.to_string()),
+            Encoding::Tid | Encoding::Compact => {
+                let (rkey, n) = tid::decode(bytes)?;
+                if n != bytes.len() {
+                    return Err(anyhow!("trailing bytes after rkey"))
+                }
+                Ok(rkey)
+            }
+        }
+    }
+
+    /// Append a DID as it appears inside keys
+    pub fn push_did(&self, did: &str, out: &mut Vec<u8>) {
+        match self {
+            Encoding::Text | Encoding::Tid => out.extend_from_slice(did.as_bytes()),
+            Encoding::Compact => did::encode_into(did, out),
+        }
+    }
+
+    /// Append an rkey as it appears inside keys
+    pub fn push_rkey(&self, rkey: &str, out: &mut Vec<u8>) {
+        match self {
+            Encoding::Text => out.extend_from_slice(rkey.as_bytes()),
+            Encoding::Tid | Encoding::Compact => tid::encode_into(rkey, out),
+        }
+    }
+
+    /// Byte that goes between liker entries in a list, if any
+    pub fn separator(&self) -> Option<u8> {
+        match self {
+            Encoding::Text => Some(b';'),
+            Encoding::Tid | Encoding::Compact => None,
+        }
+    }
+
+    /// Append liker entries to an (optional) existing list
+    pub fn join<'a>(&self, existing: Option<&[u8]>, entries: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+        let mut res = existing.map(|ex| ex.to_vec()).unwrap_or_default();
+        let mut first = existing.is_none();
+        for entry in entries {
+            match (first, self.separator()) {
+                (false, Some(sep)) => res.push(sep),
+                _ => first = false,
+            }
+            res.extend_from_slice(entry);
+        }
+        res
+    }
+
+    /// Split a liker list into its raw entries, without decoding them
+    pub fn split_likers<'a>(&self, likers: &'a [u8]) -> Result<Vec<&'a [u8]>> {
+        if let Some(sep) = self.separator() {
+            return Ok(likers.split(|b| *b == sep).collect())
+        }
+        let mut entries = vec![];
+        let mut rest = likers;
+        while !rest.is_empty() {
+            let did_len = match self {
+                Encoding::Compact => did::decode(rest)?.1,
+                _ => 1 + rest
+                    .iter()
+                    .position(|b| *b == b'!')
+                    .ok_or_else(|| anyhow!("liker entry is missing its did terminator"))?,
+            };
+            let Some(after_did) = rest.get(did_len..) else {
+                return Err(anyhow!("truncated liker entry"))
+            };
+            let (_, n) = tid::decode(after_did)?;
+            let (entry, after) = rest.split_at(did_len + n);
+            entries.push(entry);
+            rest = after;
+        }
+        Ok(entries)
+    }
+
+    /// Split a liker list back into `did!rkey` strings
+    pub fn decode_likers(&self, likers: &[u8]) -> Result<Vec<String>> {
+        match self {
+            Encoding::Text => Ok(std::str::from_utf8(likers)?
+                .split(';')
+                .map(|s| s.to_string())
+                .collect()),
+            Encoding::Tid => {
+                let mut res = vec![];
+                let mut rest = likers;
+                while !rest.is_empty() {
+                    let Some(bang) = rest.iter().position(|b| *b == b'!') else {
+                        return Err(anyhow!("liker entry is missing its did terminator"))
+                    };
+                    let did = std::str::from_utf8(&rest[..bang])?;
+                    let (rkey, n) = tid::decode(&rest[bang + 1..])?;
+                    res.push(format!("{did}!{rkey}"));
+                    rest = &rest[bang + 1 + n..];
+                }
+                Ok(res)
+            }
+            Encoding::Compact => {
+                let mut res = vec![];
+                let mut rest = likers;
+                while !rest.is_empty() {
+                    let (did, n) = did::decode(rest)?;
+                    let (rkey, m) = tid::decode(&rest[n..])?;
+                    res.push(format!("{did}!{rkey}"));
+                    rest = &rest[n + m..];
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    /// A liker list in the text form, for comparing with the sampled subjects
+    pub fn likers_text(&self, likers: &[u8]) -> Result<String> {
+        match self {
+            Encoding::Text => Ok(std::str::from_utf8(likers)?.to_string()),
+            _ => Ok(self.decode_likers(likers)?.join(";")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DID: &str = "did:plc:hdhoaan3xa3jiuq4fg4mefid";
+    const URI: &str = "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l";
+
+    #[test]
+    fn test_text_is_unchanged() {
+        let enc = Encoding::Text;
+        assert_eq!(enc.subject_key(URI), URI.as_bytes());
+        assert_eq!(enc.liker(DID, "3ld53lnvvhc2w"), format!("{DID}!3ld53lnvvhc2w").as_bytes());
+        let likers = enc.join(Some(b"a!1"), [&b"b!2"[..], &b"c!3"[..]]);
+        assert_eq!(likers, b"a!1;b!2;c!3");
+        assert_eq!(enc.join(None, [&b"a!1"[..]]), b"a!1");
+    }
+
+    #[test]
+    fn test_tid_likers_round_trip() {
+        let enc = Encoding::Tid;
+        let a = enc.liker(DID, "3ld53lnvvhc2w");
+        let b = enc.liker("did:web:example.com", "self");
+        assert_eq!(a.len(), DID.len() + 1 + tid::PACKED_LEN);
+        let likers = enc.join(Some(&a), [&b[..]]);
+        assert_eq!(
+            enc.likers_text(&likers).unwrap(),
+            format!("{DID}!3ld53lnvvhc2w;did:web:example.com!self"),
+        );
+    }
+
+    #[test]
+    fn test_split_likers_by_prefix() {
+        for enc in [Encoding::Text, Encoding::Tid, Encoding::Compact] {
+            let a = enc.liker(DID, "3ld53lnvvhc2w");
+            let b = enc.liker("did:web:example.com", "self");
+            let c = enc.liker(DID, "notatid");
+            let likers = enc.join(None, [&a[..], &b[..], &c[..]]);
+            assert_eq!(enc.split_likers(&likers).unwrap(), [&a[..], &b[..], &c[..]], "{enc}");
+            let prefix = enc.liker_prefix(DID);
+            let mine: Vec<_> = enc.split_likers(&likers).unwrap().into_iter().filter(|e| e.starts_with(&prefix)).collect();
+            assert_eq!(mine, [&a[..], &c[..]], "{enc}");
+            assert_eq!(enc.decode_rkey(&c[prefix.len()..]).unwrap(), "notatid", "{enc}");
+            // a did that's a prefix of another's isn't mistaken for it
+            assert!(!b.starts_with(&enc.liker_prefix("did:web:example.co")), "{enc}");
+        }
+    }
+
+    #[test]
+    fn test_tid_subject_key() {
+        let key = Encoding::Tid.subject_key(URI);
+        let prefix = "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/";
+        assert_eq!(&key[..prefix.len()], prefix.as_bytes());
+        assert_eq!(tid::decode(&key[prefix.len()..]).unwrap().0, "3lccjpbhjck2l");
+        // not a record uri: left alone
+        let did_uri = "at://did:plc:iyr4nadkkq2toocambsr3inz";
+        assert_eq!(Encoding::Tid.subject_key(did_uri), did_uri.as_bytes());
+    }
+
+    #[test]
+    fn test_compact_likers_round_trip() {
+        let enc = Encoding::Compact;
+        let a = enc.liker(DID, "3ld53lnvvhc2w");
+        let b = enc.liker("did:web:example.com", "self");
+        assert_eq!(a.len(), 1 + did::PLC_PACKED_LEN + tid::PACKED_LEN);
+        let likers = enc.join(None, [&a[..], &b[..]]);
+        assert_eq!(
+            enc.likers_text(&likers).unwrap(),
+            format!("{DID}!3ld53lnvvhc2w;did:web:example.com!self"),
+        );
+    }
+
+    #[test]
+    fn test_compact_subject_key() {
+        let key = Encoding::Compact.subject_key(URI);
+        let (did, n) = did::decode(&key).unwrap();
+        assert_eq!(did, "did:plc:iyr4nadkkq2toocambsr3inz");
+        let collection = b"app.bsky.feed.post/";
+        assert_eq!(&key[n..n + collection.len()], collection);
+        assert_eq!(tid::decode(&key[n + collection.len()..]).unwrap().0, "3lccjpbhjck2l");
+        assert_eq!(key.len(), 1 + did::PLC_PACKED_LEN + collection.len() + tid::PACKED_LEN);
+    }
+}
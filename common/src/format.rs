@@ -0,0 +1,75 @@
+//! everything that decides the bytes a backend writes, picked from the args
+use std::fmt;
+use anyhow::Result;
+use crate::{cli, Encoding, Layout};
+use crate::listing;
+use crate::paging::Paging;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub layout: Layout,
+    pub paging: Option<Paging>,
+    /// also write time-ordered per-like keys, for paged listing
+    pub listing: bool,
+    /// also keep a count per subject, updated with every create and delete
+    pub counts: bool,
+    /// also write a key per like under its subject and liker, for has-liked checks
+    pub liked: bool,
+    /// also keep every like under its liker, for listing what a did has liked
+    pub forward_index: bool,
+}
+
+impl Format {
+    /// `--encoding`, `--layout`, `--page-size`, `--listing`, `--counts`, `--liked` and `--forward-index`, with defaults for whatever's missing
+    pub fn from_args() -> Result<Self> {
+        Ok(Format {
+            encoding: cli::opt_or_default("encoding")?,
+            layout: cli::opt_or_default("layout")?,
+            paging: cli::opt("page-size")?,
+            listing: cli::flag("listing"),
+            counts: cli::flag("counts"),
+            liked: cli::flag("liked"),
+            forward_index: cli::flag("forward-index"),
+        })
+    }
+
+    pub fn subject_key(&self, uri: &str) -> Vec<u8> {
+        self.layout.subject_key(self.encoding, uri)
+    }
+
+    pub fn liker(&self, did: &str, rkey: &str) -> Vec<u8> {
+        self.encoding.liker(did, rkey)
+    }
+
+    pub fn like_prefix(&self, uri: &str) -> Vec<u8> {
+        self.layout.like_prefix(self.encoding, uri)
+    }
+
+    pub fn like_key(&self, uri: &str, did: &str, rkey: &str) -> Vec<u8> {
+        self.layout.like_key(self.encoding, uri, did, rkey)
+    }
+
+    /// The prefix shared by the like keys for one liker's likes of a subject
+    pub fn liked_prefix(&self, uri: &str, did: &str) -> Vec<u8> {
+        [self.like_prefix(uri), self.encoding.liker_prefix(did)].concat()
+    }
+
+    pub fn listing_key(&self, uri: &str, did: &str, rkey: &str) -> Vec<u8> {
+        listing::key(&self.subject_key(uri), did, rkey)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "encoding: {}, layout: {}, paging: ", self.encoding, self.layout)?;
+        match self.paging {
+            Some(paging) => write!(f, "{paging}")?,
+            None => write!(f, "off")?,
+        }
+        write!(f, ", listing: {}", if self.listing { "on" } else { "off" })?;
+        write!(f, ", counts: {}", if self.counts { "on" } else { "off" })?;
+        write!(f, ", liked: {}", if self.liked { "on" } else { "off" })?;
+        write!(f, ", forward index: {}", if self.forward_index { "on" } else { "off" })
+    }
+}
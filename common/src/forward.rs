@@ -0,0 +1,123 @@
+//! the forward index: every like by its liker, for "what has this account liked"
+//!
+//! with `--forward-index`, each create also writes its liker entry as a key,
+//! with the subject uri as the value, and each delete removes it. liker entries
+//! start with the did's [`Encoding::liker_prefix`] and end with the rkey, and
+//! TID rkeys sort by time, so a range scan over the prefix pages through a
+//! did's likes in either order. the keys are the same bytes as unlikes, so the
+//! index gets its own table.
+//!
+//! cursors are the encoded rkey of the last like returned.
+use anyhow::Result;
+use crate::Encoding;
+use crate::listing::{self, Cursor};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Like {
+    pub rkey: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Page {
+    /// the did's likes, in the requested order
+    pub likes: Vec<Like>,
+    /// where the next page starts, if there is one
+    pub cursor: Option<Cursor>,
+}
+
+/// Build a page from encoded rkeys and their uris that continue strictly past
+/// the cursor, in order
+pub fn page_from_entries(
+    encoding: Encoding,
+    entries: impl IntoIterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+    limit: usize,
+) -> Result<Page> {
+    let entries = entries.into_iter().map(|e| {
+        let (rkey, uri) = e?;
+        let like = Like { rkey: encoding.decode_rkey(&rkey)?, uri: String::from_utf8(uri)? };
+        Ok((rkey, like))
+    });
+    let (likes, cursor) = listing::collect_page(entries, limit)?;
+    Ok(Page { likes, cursor })
+}
+
+/// Build a page from raw keys and values scanned from [`listing::start_key`]
+/// over a did's prefix, stopping at the end of the prefix and skipping the
+/// cursor's own key
+pub fn page_from_kvs(
+    encoding: Encoding,
+    prefix: &[u8],
+    cursor: Option<&Cursor>,
+    limit: usize,
+    kvs: impl IntoIterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+) -> Result<Page> {
+    let entries = kvs
+        .into_iter()
+        .take_while(|kv| kv.as_ref().map_or(true, |(k, _)| k.starts_with(prefix)))
+        .map(|kv| kv.map(|(k, v)| (k[prefix.len()..].to_vec(), v)))
+        .filter(|kv| kv.as_ref().map_or(true, |(e, _)| Some(&e[..]) != cursor.map(Cursor::entry)));
+    page_from_entries(encoding, entries, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::listing::Order;
+    use crate::tid;
+
+    const DID: &str = "did:plc:hdhoaan3xa3jiuq4fg4mefid";
+
+    fn likes() -> Vec<Like> {
+        (0..5)
+            .map(|i| Like {
+                rkey: tid::format(1_000_000 + i),
+                uri: format!("at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/{i}"),
+            })
+            .collect()
+    }
+
+    fn store(encoding: Encoding) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut kv = BTreeMap::new();
+        for like in likes() {
+            kv.insert(encoding.liker(DID, &like.rkey), like.uri.into_bytes());
+        }
+        // neighbours on both sides, including a did that extends this one
+        kv.insert(encoding.liker("did:plc:hdhoaan3xa3jiuq4fg4mefic", "self"), b"before".to_vec());
+        kv.insert(encoding.liker("did:plc:hdhoaan3xa3jiuq4fg4mefie", "self"), b"after".to_vec());
+        kv
+    }
+
+    fn list(kv: &BTreeMap<Vec<u8>, Vec<u8>>, encoding: Encoding, order: Order, limit: usize, cursor: Option<&Cursor>) -> Page {
+        let prefix = encoding.liker_prefix(DID);
+        let start = listing::start_key(&prefix, order, cursor);
+        let kvs: Vec<_> = match order {
+            Order::OldestFirst => kv.range(start..).map(|(k, v)| Ok((k.clone(), v.clone()))).collect(),
+            Order::NewestFirst => kv.range(..start).rev().map(|(k, v)| Ok((k.clone(), v.clone()))).collect(),
+        };
+        page_from_kvs(encoding, &prefix, cursor, limit, kvs).unwrap()
+    }
+
+    #[test]
+    fn test_pages_through_one_did() {
+        for encoding in [Encoding::Text, Encoding::Tid, Encoding::Compact] {
+            let kv = store(encoding);
+            for (order, limit) in [(Order::OldestFirst, 2), (Order::NewestFirst, 2), (Order::NewestFirst, 5)] {
+                let mut listed = vec![];
+                let mut cursor = None;
+                loop {
+                    let page = list(&kv, encoding, order, limit, cursor.as_ref());
+                    listed.extend(page.likes);
+                    let Some(next) = page.cursor else { break };
+                    cursor = Some(next);
+                }
+                let mut expected = likes();
+                if order == Order::NewestFirst {
+                    expected.reverse();
+                }
+                assert_eq!(listed, expected, "{encoding} {order} by {limit}");
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! latency histograms for the read benchmarks
+//!
+//! HDR-style: values (nanos) are counted in buckets that are exact below
+//! `2^SUB_BITS` and then split each power of two into `2^(SUB_BITS - 1)` even
+//! steps, so any percentile comes back within 0.4% of the real value no matter
+//! how long the tail gets. histograms merge by adding counts, so passes and
+//! threads can each keep their own.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+use tinyjson::JsonValue;
+
+const SUB_BITS: u32 = 9;
+const HALF: usize = 1 << (SUB_BITS - 1);
+
+/// The percentiles printed and exported, with their names
+pub const PERCENTILES: [(&str, f64); 4] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999)];
+
+fn index(nanos: u64) -> usize {
+    let shift = (64 - nanos.leading_zeros()).saturating_sub(SUB_BITS);
+    shift as usize * HALF + (nanos >> shift) as usize
+}
+
+/// The smallest and largest values counted in a bucket
+fn bounds(index: usize) -> (u64, u64) {
+    let shift = (index / HALF).saturating_sub(1) as u32;
+    let low = ((index - shift as usize * HALF) as u64) << shift;
+    (low, low + ((1 << shift) - 1))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    sum: u128,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, d: Duration) {
+        self.record_nanos(d.as_nanos() as u64);
+    }
+
+    pub fn record_nanos(&mut self, nanos: u64) {
+        let i = index(nanos);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        self.total += 1;
+        self.sum += nanos as u128;
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (mine, theirs) in self.counts.iter_mut().zip(&other.counts) {
+            *mine += theirs;
+        }
+        self.total += other.total;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0
+        }
+        self.sum as f64 / self.total as f64
+    }
+
+    /// The value at quantile `q` (0 to 1): the largest value that could be in
+    /// the bucket holding that rank, or 0 if empty
+    pub fn value_at(&self, q: f64) -> u64 {
+        if self.total == 0 {
+            return 0
+        }
+        let rank = ((q * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bounds(i).1.min(self.max)
+            }
+        }
+        self.max
+    }
+
+    /// Counts, mean, percentiles and max in nanos, plus every non-empty
+    /// bucket as `[smallest value, count]`
+    pub fn to_json(&self) -> JsonValue {
+        let mut obj = HashMap::new();
+        obj.insert("count".to_string(), JsonValue::Number(self.total as f64));
+        obj.insert("mean".to_string(), JsonValue::Number(self.mean()));
+        for (name, q) in PERCENTILES {
+            obj.insert(name.to_string(), JsonValue::Number(self.value_at(q) as f64));
+        }
+        obj.insert("max".to_string(), JsonValue::Number(self.max as f64));
+        let buckets = self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| JsonValue::Array(vec![
+                JsonValue::Number(bounds(i).0 as f64),
+                JsonValue::Number(*count as f64),
+            ]))
+            .collect();
+        obj.insert("buckets".to_string(), JsonValue::Array(buckets));
+        JsonValue::Object(obj)
+    }
+}
+
+/// Mean, percentiles and max in micros, then the count, tab-separated
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let micros = |nanos: u64| nanos as f64 / 1000.0;
+        write!(f, "{:.3}", self.mean() / 1000.0)?;
+        for (_, q) in PERCENTILES {
+            write!(f, "\t{:.3}", micros(self.value_at(q)))?;
+        }
+        write!(f, "\t{:.3}\t{}", micros(self.max), self.total)
+    }
+}
+
+/// Histograms by group (like count, page depth, ...), and one over everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub all: Histogram,
+    pub groups: BTreeMap<usize, Histogram>,
+}
+
+impl Timings {
+    pub fn record(&mut self, group: usize, d: Duration) {
+        self.record_nanos(group, d.as_nanos() as u64);
+    }
+
+    pub fn record_nanos(&mut self, group: usize, nanos: u64) {
+        self.all.record_nanos(nanos);
+        self.groups.entry(group).or_default().record_nanos(nanos);
+    }
+
+    pub fn merge(&mut self, other: &Timings) {
+        self.all.merge(&other.all);
+        for (group, histogram) in &other.groups {
+            self.groups.entry(*group).or_default().merge(histogram);
+        }
+    }
+
+    /// A row per group, smallest first, then one for everything
+    pub fn print(&self) {
+        println!("group\tmean\tp50\tp90\tp99\tp99.9\tmax\tcount");
+        for (group, histogram) in &self.groups {
+            println!("{group}\t{histogram}");
+        }
+        println!("all\t{}", self.all);
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let groups = self.groups
+            .iter()
+            .map(|(group, histogram)| (group.to_string(), histogram.to_json()))
+            .collect();
+        JsonValue::Object(HashMap::from([
+            ("all".to_string(), self.all.to_json()),
+            ("groups".to_string(), JsonValue::Object(groups)),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_cover_every_value_once() {
+        let mut last = None;
+        for i in 0..HALF * 40 {
+            let (low, high) = bounds(i);
+            assert!(low <= high);
+            assert_eq!(index(low), i);
+            assert_eq!(index(high), i);
+            if let Some(last) = last {
+                assert_eq!(low, last + 1, "gap before bucket {i}");
+            }
+            last = Some(high);
+        }
+        assert_eq!(bounds(index(u64::MAX)).1, u64::MAX);
+    }
+
+    #[test]
+    fn test_percentiles_are_close() {
+        let mut h = Histogram::default();
+        for nanos in 1..=100_000 {
+            h.record_nanos(nanos * 10);
+        }
+        for (_, q) in PERCENTILES {
+            let exact = (q * 100_000.0) * 10.0;
+            let found = h.value_at(q) as f64;
+            assert!((found - exact).abs() / exact < 0.004, "{q}: {found} vs {exact}");
+        }
+        assert_eq!(h.value_at(1.0), 1_000_000);
+        assert_eq!(h.max(), 1_000_000);
+        assert_eq!(h.len(), 100_000);
+        assert_eq!(h.mean(), 500_005.0);
+        assert_eq!(Histogram::default().value_at(0.5), 0);
+    }
+
+    #[test]
+    fn test_small_values_are_exact() {
+        let mut h = Histogram::default();
+        for nanos in [3, 3, 7, 200] {
+            h.record_nanos(nanos);
+        }
+        assert_eq!(h.value_at(0.5), 3);
+        assert_eq!(h.value_at(0.75), 7);
+        assert_eq!(h.value_at(0.99), 200);
+    }
+
+    #[test]
+    fn test_merge_matches_recording_together() {
+        let (mut a, mut b, mut both) = (Timings::default(), Timings::default(), Timings::default());
+        for i in 0..1000u64 {
+            let (group, nanos) = ((i % 7) as usize, i * i);
+            if i % 3 == 0 { &mut a } else { &mut b }.record_nanos(group, nanos);
+            both.record_nanos(group, nanos);
+        }
+        a.merge(&b);
+        assert_eq!(a, both);
+        let mut empty = Timings::default();
+        empty.merge(&both);
+        assert_eq!(empty, both);
+    }
+
+    #[test]
+    fn test_json() {
+        let mut t = Timings::default();
+        t.record_nanos(5, 1500);
+        t.record_nanos(5, 2500);
+        let json = t.to_json().stringify().unwrap();
+        let parsed: JsonValue = json.parse().unwrap();
+        let all: &HashMap<_, _> = parsed["all"].get().unwrap();
+        assert_eq!(all["count"], JsonValue::Number(2.0));
+        assert_eq!(all["max"], JsonValue::Number(2500.0));
+        let groups: &HashMap<_, _> = parsed["groups"].get().unwrap();
+        assert!(groups.contains_key("5"));
+    }
+}
@@ -0,0 +1,275 @@
+//! orderings for subject keys, to see how key locality affects space and compaction
+//!
+//! - `Forward`: the uri as-is, so an author's records are together
+//! - `Reversed`: `collection\rkey\did` like the `pebble-rkey` experiment, so
+//!   records cluster by creation time
+//! - `DidPrefixed`: `did\rkey\collection`, an author's records in time order
+//!   regardless of collection
+//! - `Hashed`: a hash of the forward key in front of it, so there's no
+//!   locality at all
+//!
+//! uris that aren't `at://did/collection/rkey` always get the forward layout
+//! (behind the hash, for `Hashed`).
+//!
+//! `Forward` and `DidPrefixed` keep each author's records under one prefix, so
+//! their subject keys can be scanned by authority and decoded back to uris.
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use crate::encoding::{split_record, Encoding};
+use crate::{did, tid};
+
+pub const HASH_PREFIX_LEN: usize = 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    Forward,
+    Reversed,
+    DidPrefixed,
+    Hashed,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "forward" => Ok(Layout::Forward),
+            "reversed" => Ok(Layout::Reversed),
+            "did-prefixed" => Ok(Layout::DidPrefixed),
+            "hashed" => Ok(Layout::Hashed),
+            _ => Err(anyhow!("unknown layout {s:?}, expected 'forward', 'reversed', 'did-prefixed', or 'hashed'")),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Layout::Forward => "forward",
+            Layout::Reversed => "reversed",
+            Layout::DidPrefixed => "did-prefixed",
+            Layout::Hashed => "hashed",
+        })
+    }
+}
+
+/// 64-bit FNV-1a: stable across runs and platforms, which is all we need here
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+impl Layout {
+    /// The key for a liked subject
+    pub fn subject_key(&self, encoding: Encoding, uri: &str) -> Vec<u8> {
+        let parts = split_record(uri);
+        match (self, parts) {
+            (Layout::Reversed, Some((did, collection, rkey))) => {
+                let mut key = collection.as_bytes().to_vec();
+                key.push(b'\\');
+                encoding.push_rkey(rkey, &mut key);
+                key.push(b'\\');
+                encoding.push_did(did, &mut key);
+                key
+            }
+            (Layout::DidPrefixed, Some((did, collection, rkey))) => {
+                let mut key = vec![];
+                encoding.push_did(did, &mut key);
+                key.push(b'\\');
+                encoding.push_rkey(rkey, &mut key);
+                key.push(b'\\');
+                key.extend_from_slice(collection.as_bytes());
+                key
+            }
+            (Layout::Hashed, _) => {
+                let forward = encoding.subject_key(uri);
+                let hash = fnv1a(&forward).to_be_bytes();
+                [&hash[..HASH_PREFIX_LEN], &forward].concat()
+            }
+            _ => encoding.subject_key(uri),
+        }
+    }
+
+    /// The prefix shared by all of a subject's like keys
+    pub fn like_prefix(&self, encoding: Encoding, uri: &str) -> Vec<u8> {
+        let mut prefix = self.subject_key(encoding, uri);
+        prefix.push(b'!');
+        prefix
+    }
+
+    /// The key for a single like, for stores that keep one key per like
+    pub fn like_key(&self, encoding: Encoding, uri: &str, did: &str, rkey: &str) -> Vec<u8> {
+        [self.like_prefix(encoding, uri), encoding.liker(did, rkey)].concat()
+    }
+
+    /// The prefix shared by the subject keys of every record of a DID, or of
+    /// just its records in one collection if the layout puts that next
+    pub fn authority_prefix(&self, encoding: Encoding, did: &str, collection: Option<&str>) -> Result<Vec<u8>> {
+        let mut prefix = vec![];
+        match (self, encoding) {
+            (Layout::Forward, Encoding::Compact) => encoding.push_did(did, &mut prefix),
+            (Layout::Forward, _) => {
+                prefix.extend_from_slice(b"at://");
+                encoding.push_did(did, &mut prefix);
+                prefix.push(b'/');
+            }
+            (Layout::DidPrefixed, _) => {
+                encoding.push_did(did, &mut prefix);
+                prefix.push(b'\\');
+                return Ok(prefix)
+            }
+            (Layout::Reversed | Layout::Hashed, _) => {
+                return Err(anyhow!("the {self} layout scatters an author's records, so it can't scan by authority"))
+            }
+        }
+        if let Some(collection) = collection {
+            prefix.extend_from_slice(collection.as_bytes());
+            prefix.push(b'/');
+        }
+        Ok(prefix)
+    }
+
+    /// Decode the record subject key at the start of `key` back to its uri,
+    /// with the number of bytes it took. only for the layouts that can scan by
+    /// authority.
+    pub fn decode_subject(&self, encoding: Encoding, key: &[u8]) -> Result<(String, usize)> {
+        // what can follow a subject key: a like key's liker, a page number or a listing entry
+        const AFTER: &[u8] = b"!#@";
+        let mut r = KeyReader { key, at: 0 };
+        let uri = match self {
+            Layout::Forward => {
+                if encoding != Encoding::Compact {
+                    r.literal(b"at://")?;
+                }
+                let did = r.did(encoding, b"/")?;
+                if encoding != Encoding::Compact {
+                    r.literal(b"/")?;
+                }
+                let collection = r.text(b"/")?;
+                r.literal(b"/")?;
+                let rkey = r.rkey(encoding, AFTER)?;
+                format!("at://{did}/{collection}/{rkey}")
+            }
+            Layout::DidPrefixed => {
+                let did = r.did(encoding, b"\\")?;
+                r.literal(b"\\")?;
+                let rkey = r.rkey(encoding, b"\\")?;
+                r.literal(b"\\")?;
+                let collection = r.text(AFTER)?;
+                format!("at://{did}/{collection}/{rkey}")
+            }
+            Layout::Reversed | Layout::Hashed => return Err(anyhow!("can't decode {self} subject keys")),
+        };
+        Ok((uri, r.at))
+    }
+}
+
+/// Reads the parts of a subject key in order
+struct KeyReader<'a> {
+    key: &'a [u8],
+    at: usize,
+}
+
+impl<'a> KeyReader<'a> {
+    fn literal(&mut self, expected: &[u8]) -> Result<()> {
+        if !self.key[self.at..].starts_with(expected) {
+            return Err(anyhow!("subject key is missing {:?} at byte {}", String::from_utf8_lossy(expected), self.at))
+        }
+        self.at += expected.len();
+        Ok(())
+    }
+
+    /// Text up to the first of `ends`, or the end of the key
+    fn text(&mut self, ends: &[u8]) -> Result<&'a str> {
+        let rest = &self.key[self.at..];
+        let n = rest.iter().position(|b| ends.contains(b)).unwrap_or(rest.len());
+        self.at += n;
+        Ok(std::str::from_utf8(&rest[..n])?)
+    }
+
+    fn did(&mut self, encoding: Encoding, ends: &[u8]) -> Result<String> {
+        match encoding {
+            Encoding::Text | Encoding::Tid => Ok(self.text(ends)?.to_string()),
+            Encoding::Compact => {
+                let (did, n) = did::decode(&self.key[self.at..])?;
+                self.at += n;
+                Ok(did)
+            }
+        }
+    }
+
+    fn rkey(&mut self, encoding: Encoding, ends: &[u8]) -> Result<String> {
+        match encoding {
+            Encoding::Text => Ok(self.text(ends)?.to_string()),
+            Encoding::Tid | Encoding::Compact => {
+                let (rkey, n) = tid::decode(&self.key[self.at..])?;
+                self.at += n;
+                Ok(rkey)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l";
+
+    #[test]
+    fn test_text_layouts() {
+        let enc = Encoding::Text;
+        assert_eq!(Layout::Forward.subject_key(enc, URI), URI.as_bytes());
+        assert_eq!(
+            Layout::Reversed.subject_key(enc, URI),
+            br"app.bsky.feed.post\3lccjpbhjck2l\did:plc:iyr4nadkkq2toocambsr3inz",
+        );
+        assert_eq!(
+            Layout::DidPrefixed.subject_key(enc, URI),
+            br"did:plc:iyr4nadkkq2toocambsr3inz\3lccjpbhjck2l\app.bsky.feed.post",
+        );
+        let hashed = Layout::Hashed.subject_key(enc, URI);
+        assert_eq!(&hashed[HASH_PREFIX_LEN..], URI.as_bytes());
+        assert_eq!(hashed, Layout::Hashed.subject_key(enc, URI));
+    }
+
+    #[test]
+    fn test_reversed_sorts_by_rkey() {
+        let older = "at://did:plc:zzzzzzzzzzzzzzzzzzzzzzzz/app.bsky.feed.post/3lccjpbhjck2l";
+        let newer = "at://did:plc:222222222222222222222222/app.bsky.feed.post/3ld53lnvvhc2w";
+        for enc in [Encoding::Text, Encoding::Tid, Encoding::Compact] {
+            assert!(Layout::Reversed.subject_key(enc, older) < Layout::Reversed.subject_key(enc, newer));
+            assert!(Layout::Forward.subject_key(enc, older) > Layout::Forward.subject_key(enc, newer));
+        }
+    }
+
+    #[test]
+    fn test_like_keys_share_prefix() {
+        for layout in [Layout::Forward, Layout::Reversed, Layout::DidPrefixed, Layout::Hashed] {
+            let prefix = layout.like_prefix(Encoding::Compact, URI);
+            let key = layout.like_key(Encoding::Compact, URI, "did:plc:hdhoaan3xa3jiuq4fg4mefid", "3ld53lnvvhc2w");
+            assert!(key.starts_with(&prefix));
+        }
+    }
+
+    #[test]
+    fn test_authority_prefix_and_decode() {
+        let did = "did:plc:iyr4nadkkq2toocambsr3inz";
+        for enc in [Encoding::Text, Encoding::Tid, Encoding::Compact] {
+            for layout in [Layout::Forward, Layout::DidPrefixed] {
+                let key = layout.like_key(enc, URI, "did:plc:hdhoaan3xa3jiuq4fg4mefid", "3ld53lnvvhc2w");
+                assert!(key.starts_with(&layout.authority_prefix(enc, did, None).unwrap()), "{layout} {enc}");
+                assert!(key.starts_with(&layout.authority_prefix(enc, did, Some("app.bsky.feed.post")).unwrap()));
+                assert!(!key.starts_with(&layout.authority_prefix(enc, "did:plc:iyr4nadkkq2toocambsr3in", None).unwrap()));
+                let subject = layout.subject_key(enc, URI);
+                assert_eq!(layout.decode_subject(enc, &key).unwrap(), (URI.to_string(), subject.len()), "{layout} {enc}");
+                assert_eq!(layout.decode_subject(enc, &subject).unwrap(), (URI.to_string(), subject.len()));
+            }
+            // the collection only narrows the forward layout
+            assert!(!Layout::Forward.subject_key(enc, URI)
+                .starts_with(&Layout::Forward.authority_prefix(enc, did, Some("app.bsky.feed.like")).unwrap()));
+            assert!(Layout::Reversed.authority_prefix(enc, did, None).is_err());
+            assert!(Layout::Hashed.authority_prefix(enc, did, None).is_err());
+        }
+    }
+}
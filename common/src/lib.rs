@@ -0,0 +1,26 @@
+//! bits shared by the rust backends
+pub mod aturi;
+pub mod authority;
+pub mod bench;
+pub mod bulk;
+pub mod cli;
+pub mod counts;
+pub mod dict;
+pub mod durability;
+pub mod did;
+pub mod encoding;
+pub mod format;
+pub mod forward;
+pub mod histogram;
+pub mod layout;
+pub mod listing;
+pub mod mixed;
+pub mod paging;
+pub mod phase;
+pub mod pipeline;
+pub mod tid;
+pub mod verify;
+
+pub use encoding::Encoding;
+pub use format::Format;
+pub use layout::Layout;
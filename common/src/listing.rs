@@ -0,0 +1,298 @@
+//! time-ordered per-like keys, for paging through a subject's likers
+//!
+//! with `--listing`, every like also gets a key `<subject key>@<entry>`, where
+//! the entry is the like's encoded rkey followed by the liker's encoded DID.
+//! like rkeys are TIDs, so a subject's entries sort oldest-first, and a range
+//! scan in either direction lists its likers by time. non-TID rkeys sort after
+//! all TIDs.
+//!
+//! cursors are the hex of the last entry returned, and pages continue strictly
+//! after (or before, newest-first) it.
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use crate::{did, tid};
+
+pub const LISTING_SEP: u8 = b'@';
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+impl FromStr for Order {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "newest" => Ok(Order::NewestFirst),
+            "oldest" => Ok(Order::OldestFirst),
+            _ => Err(anyhow!("unknown order {s:?}, expected newest or oldest")),
+        }
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Order::NewestFirst => write!(f, "newest"),
+            Order::OldestFirst => write!(f, "oldest"),
+        }
+    }
+}
+
+/// An opaque position to resume listing from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(Vec<u8>);
+
+impl Cursor {
+    /// The entry this cursor points at
+    pub fn entry(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        s.as_bytes()
+            .chunks(2)
+            .map(|pair| match pair {
+                [hi, lo] => std::str::from_utf8(&[*hi, *lo]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()),
+                _ => None,
+            })
+            .collect::<Option<_>>()
+            .map(Cursor)
+            .ok_or_else(|| anyhow!("malformed cursor"))
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Page {
+    /// `did!rkey`s, in the requested order
+    pub likers: Vec<String>,
+    /// where the next page starts, if there is one
+    pub cursor: Option<Cursor>,
+}
+
+/// The sortable entry for one like: encoded rkey, then encoded did
+pub fn entry(did: &str, rkey: &str) -> Vec<u8> {
+    let mut entry = tid::encode(rkey);
+    did::encode_into(did, &mut entry);
+    entry
+}
+
+pub fn decode_entry(entry: &[u8]) -> Result<String> {
+    let (rkey, n) = tid::decode(entry)?;
+    let (did, m) = did::decode(&entry[n..])?;
+    if n + m != entry.len() {
+        return Err(anyhow!("trailing bytes after listing entry"))
+    }
+    Ok(format!("{did}!{rkey}"))
+}
+
+/// `<subject key>@`: every listing key for the subject starts with this
+pub fn prefix(subject_key: &[u8]) -> Vec<u8> {
+    let mut prefix = subject_key.to_vec();
+    prefix.push(LISTING_SEP);
+    prefix
+}
+
+pub fn key(subject_key: &[u8], did: &str, rkey: &str) -> Vec<u8> {
+    [prefix(subject_key), entry(did, rkey)].concat()
+}
+
+/// Where a range scan should start: forward from here for oldest-first, or
+/// backward from just before here for newest-first. reverse scans must leave
+/// this key out, since without a cursor it's the first key past the prefix,
+/// which can be a neighbouring subject (`<subject>A` after `<subject>@`). a
+/// forward scan's cursor key, if it's there, is skipped by [`page_from_keys`].
+pub fn start_key(prefix: &[u8], order: Order, cursor: Option<&Cursor>) -> Vec<u8> {
+    match (cursor, order) {
+        (Some(cursor), _) => [prefix, cursor.entry()].concat(),
+        (None, Order::OldestFirst) => prefix.to_vec(),
+        (None, Order::NewestFirst) => after_prefix(prefix),
+    }
+}
+
+/// The smallest key that sorts after every key with the prefix
+pub fn after_prefix(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while end.last() == Some(&u8::MAX) {
+        end.pop();
+    }
+    *end.last_mut().expect("prefix isn't all 0xff") += 1;
+    end
+}
+
+/// Collect up to `limit` decoded items from entries that continue strictly
+/// past the cursor, in order, with the cursor for the next page if there is
+/// one. reads at most `limit + 1`, to know.
+pub fn collect_page<T>(
+    entries: impl IntoIterator<Item = Result<(Vec<u8>, T)>>,
+    limit: usize,
+) -> Result<(Vec<T>, Option<Cursor>)> {
+    let mut items = vec![];
+    let mut last = None;
+    for entry in entries.into_iter().take(limit + 1) {
+        let (entry, item) = entry?;
+        if items.len() == limit {
+            return Ok((items, last.map(Cursor)))
+        }
+        items.push(item);
+        last = Some(entry);
+    }
+    Ok((items, None))
+}
+
+/// Build a page from entries that continue strictly past the cursor, in
+/// order. reads at most `limit + 1`, to know if there's another page.
+pub fn page_from_entries(entries: impl IntoIterator<Item = Result<Vec<u8>>>, limit: usize) -> Result<Page> {
+    let entries = entries.into_iter().map(|e| {
+        let e = e?;
+        let liker = decode_entry(&e)?;
+        Ok((e, liker))
+    });
+    let (likers, cursor) = collect_page(entries, limit)?;
+    Ok(Page { likers, cursor })
+}
+
+/// Build a page from raw keys scanned from [`start_key`] in the listing order,
+/// stopping at the end of the prefix and skipping the cursor's own key
+pub fn page_from_keys(
+    prefix: &[u8],
+    cursor: Option<&Cursor>,
+    limit: usize,
+    keys: impl IntoIterator<Item = Result<Vec<u8>>>,
+) -> Result<Page> {
+    let entries = keys
+        .into_iter()
+        .take_while(|k| k.as_ref().map_or(true, |k| k.starts_with(prefix)))
+        .map(|k| k.map(|k| k[prefix.len()..].to_vec()))
+        .filter(|e| e.as_ref().map_or(true, |e| Some(&e[..]) != cursor.map(Cursor::entry)));
+    page_from_entries(entries, limit)
+}
+
+/// The `did!rkey`s of a subject's likers, in the order listing returns them
+pub fn expected_order(likers: &[&str], order: Order) -> Result<Vec<String>> {
+    let mut sorted = likers
+        .iter()
+        .map(|l| {
+            let (did, rkey) = l.split_once('!').ok_or_else(|| anyhow!("liker {l:?} is not did!rkey"))?;
+            Ok((entry(did, rkey), l.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    sorted.sort();
+    if order == Order::NewestFirst {
+        sorted.reverse();
+    }
+    Ok(sorted.into_iter().map(|(_, l)| l).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SUBJECT: &[u8] = b"at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l";
+
+    fn likers() -> Vec<String> {
+        (0..7)
+            .map(|i| format!("did:plc:hdhoaan3xa3jiuq4fg4mef{i}a!{}", tid::format(1_000_000 - i)))
+            .chain(["did:web:example.com!self".to_string()])
+            .collect()
+    }
+
+    /// A fake kv store, with the subject's likes and some neighbouring keys
+    fn store() -> BTreeMap<Vec<u8>, ()> {
+        let mut kv = BTreeMap::new();
+        for l in likers() {
+            let (did, rkey) = l.split_once('!').unwrap();
+            kv.insert(key(SUBJECT, did, rkey), ());
+        }
+        kv.insert(SUBJECT.to_vec(), ());
+        kv.insert([SUBJECT, b"#page"].concat(), ());
+        kv.insert(key(b"at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2m", "did:plc:x", "self"), ());
+        kv
+    }
+
+    fn list(kv: &BTreeMap<Vec<u8>, ()>, order: Order, limit: usize, cursor: Option<&Cursor>) -> Page {
+        let prefix = prefix(SUBJECT);
+        let start = start_key(&prefix, order, cursor);
+        let keys: Vec<_> = match order {
+            Order::OldestFirst => kv.range(start..).map(|(k, _)| Ok(k.clone())).collect(),
+            Order::NewestFirst => kv.range(..start).rev().map(|(k, _)| Ok(k.clone())).collect(),
+        };
+        page_from_keys(&prefix, cursor, limit, keys).unwrap()
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        for l in likers() {
+            let (did, rkey) = l.split_once('!').unwrap();
+            assert_eq!(decode_entry(&entry(did, rkey)).unwrap(), l);
+        }
+    }
+
+    #[test]
+    fn test_pages_cover_everything_in_order() {
+        let kv = store();
+        let all = likers();
+        let all: Vec<_> = all.iter().map(|l| &l[..]).collect();
+        for order in [Order::OldestFirst, Order::NewestFirst] {
+            for limit in [1, 3, 8, 100] {
+                let mut listed = vec![];
+                let mut cursor = None;
+                loop {
+                    let page = list(&kv, order, limit, cursor.as_ref());
+                    assert!(page.likers.len() <= limit);
+                    listed.extend(page.likers);
+                    let Some(next) = page.cursor else { break };
+                    // cursors survive a trip through their opaque string form
+                    cursor = Some(next.to_string().parse().unwrap());
+                }
+                assert_eq!(listed, expected_order(&all, order).unwrap(), "{order} by {limit}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_oldest_first_is_by_tid() {
+        let page = list(&store(), Order::OldestFirst, 2, None);
+        assert_eq!(page.likers, [likers()[6].clone(), likers()[5].clone()]);
+        let page = list(&store(), Order::NewestFirst, 1, None);
+        assert_eq!(page.likers, ["did:web:example.com!self"]);
+    }
+
+    #[test]
+    fn test_newest_first_skips_a_colliding_neighbour() {
+        let mut kv = store();
+        // a subject whose key is this one's plus `A`, right where the reverse scan starts
+        let neighbour = [SUBJECT, b"A"].concat();
+        assert_eq!(neighbour, start_key(&prefix(SUBJECT), Order::NewestFirst, None));
+        kv.insert(neighbour.clone(), ());
+        kv.insert(key(&neighbour, "did:plc:x", "self"), ());
+        let page = list(&kv, Order::NewestFirst, 2, None);
+        assert_eq!(page.likers, ["did:web:example.com!self".to_string(), likers()[0].clone()]);
+    }
+
+    #[test]
+    fn test_after_prefix() {
+        assert_eq!(after_prefix(b"abc@"), b"abcA");
+        assert_eq!(after_prefix(&[0x01, 0x7f, 0xff, 0xff]), [0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_bad_cursor() {
+        assert!("abc".parse::<Cursor>().is_err());
+        assert!("zz".parse::<Cursor>().is_err());
+        assert_eq!("00ff".parse::<Cursor>().unwrap().entry(), [0x00, 0xff]);
+    }
+}
@@ -0,0 +1,210 @@
+//! reads running alongside ingest, with `--readers N`
+//!
+//! each reader thread makes its own read function (so sqlite readers can have
+//! their own connections) and loops over the sampled subjects from its own
+//! offset until ingest is done, timing every read. each checkin then gets the
+//! write throughput since the last one and a latency histogram of the reads
+//! that finished in between as extra columns. a reader that fails stops the
+//! others, and ingest stops at its next checkin with the reader's error.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+use anyhow::{anyhow, Result};
+
+use crate::cli;
+use crate::histogram::Histogram;
+
+/// how many reads each reader times on its own before adding them to the
+/// shared histogram, to keep the readers from contending on it
+const READER_FLUSH: u64 = 100;
+
+/// Read latencies so far, shared between the readers and ingest
+pub struct Reads {
+    readers: usize,
+    /// the reads since the last checkin
+    recent: Mutex<Histogram>,
+    /// every read before the last checkin
+    all: Mutex<Histogram>,
+    /// when the last checkin was, and how many entries had been written
+    last: Mutex<(Instant, u64)>,
+    done: AtomicBool,
+    /// the first reader error, until ingest picks it up
+    failed: Mutex<Option<anyhow::Error>>,
+}
+
+impl Reads {
+    fn new(readers: usize) -> Self {
+        Reads {
+            readers,
+            recent: Mutex::new(Histogram::default()),
+            all: Mutex::new(Histogram::default()),
+            last: Mutex::new((Instant::now(), 0)),
+            done: AtomicBool::new(false),
+            failed: Mutex::new(None),
+        }
+    }
+
+    /// Keep a reader's error for ingest, and stop the other readers
+    fn fail(&self, e: anyhow::Error) {
+        self.failed.lock().unwrap().get_or_insert(e.context("a reader failed"));
+        self.done.store(true, Ordering::Relaxed);
+    }
+
+    /// A reader's error, if one has failed, so ingest can stop instead of
+    /// carrying on with fewer readers. call it at each checkin.
+    pub fn check(&self) -> Result<()> {
+        match self.failed.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a reader's own histogram of its latest reads
+    fn add(&self, reads: &mut Histogram) {
+        self.recent.lock().unwrap().merge(reads);
+        *reads = Histogram::default();
+    }
+
+    /// The checkin columns for the interval since the last one: nothing
+    /// without readers, otherwise writes per second and the reads' histogram
+    pub fn checkin(&self, entries: u64) -> Checkin {
+        if self.readers == 0 {
+            return Checkin(None)
+        }
+        let recent = std::mem::take(&mut *self.recent.lock().unwrap());
+        let mut last = self.last.lock().unwrap();
+        let now = Instant::now();
+        let writes = (entries - last.1) as f64 / (now - last.0).as_secs_f64();
+        *last = (now, entries);
+        self.all.lock().unwrap().merge(&recent);
+        Checkin(Some((writes, recent)))
+    }
+
+    /// Every read of the run
+    fn overall(&self) -> Histogram {
+        let mut all = self.all.lock().unwrap();
+        all.merge(&self.recent.lock().unwrap());
+        all.clone()
+    }
+}
+
+/// Extra columns for a checkin line, starting with a tab
+pub struct Checkin(Option<(f64, Histogram)>);
+
+impl fmt::Display for Checkin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some((writes, reads)) => write!(f, "\t{writes:.0}\t{reads}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `--readers`, defaulting to none
+pub fn readers() -> Result<usize> {
+    Ok(cli::opt("readers")?.unwrap_or(0))
+}
+
+/// Run `ingest` with `readers` threads reading sampled subjects alongside it
+/// until it returns, then print percentiles over all of their reads.
+///
+/// each reader thread calls `new_reader` once for its read function, which
+/// gets subject uris.
+pub fn run<R, T>(
+    readers: usize,
+    subjects_path: &str,
+    new_reader: impl Fn() -> Result<R> + Sync,
+    ingest: impl FnOnce(&Reads) -> Result<T>,
+) -> Result<T>
+where
+    R: FnMut(&str) -> Result<()>,
+{
+    let reads = Reads::new(readers);
+    if readers == 0 {
+        return ingest(&reads)
+    }
+    let mut uris = vec![];
+    for line in io::BufReader::new(File::open(subjects_path)?).lines() {
+        let line = line?;
+        let Some((uri, _)) = line.split_once('|') else {
+            return Err(anyhow!("failed to split input"))
+        };
+        uris.push(uri.to_string());
+    }
+    println!("readers: {readers} (checkins add writes/sec, then read mean/p50/p90/p99/p99.9/max micros and count)");
+
+    let ingested = thread::scope(|s| -> Result<T> {
+        let handles: Vec<_> = (0..readers)
+            .map(|i| {
+                let (reads, uris, new_reader) = (&reads, &uris, &new_reader);
+                s.spawn(move || {
+                    let read_loop = || -> Result<()> {
+                        let mut read = new_reader()?;
+                        let mut mine = Histogram::default();
+                        for uri in uris.iter().cycle().skip(i * uris.len() / readers) {
+                            if reads.done.load(Ordering::Relaxed) {
+                                break
+                            }
+                            let t0 = Instant::now();
+                            read(uri)?;
+                            mine.record(t0.elapsed());
+                            if mine.len() == READER_FLUSH {
+                                reads.add(&mut mine);
+                            }
+                        }
+                        reads.add(&mut mine);
+                        Ok(())
+                    };
+                    if let Err(e) = read_loop() {
+                        reads.fail(e);
+                    }
+                })
+            })
+            .collect();
+        let ingested = ingest(&reads);
+        reads.done.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.join().map_err(|_| anyhow!("a reader panicked"))?;
+        }
+        // a reader can fail after the last checkin
+        let ingested = ingested?;
+        reads.check()?;
+        Ok(ingested)
+    })?;
+
+    println!("mean\tp50\tp90\tp99\tp99.9\tmax\tcount");
+    println!("{}", reads.overall());
+    Ok(ingested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_readers_adds_no_columns() {
+        let reads = Reads::new(0);
+        assert_eq!(reads.checkin(10).to_string(), "");
+        let ran = run(0, "no such file", || Ok(|_: &str| Ok(())), |_| Ok(3)).unwrap();
+        assert_eq!(ran, 3);
+    }
+
+    #[test]
+    fn test_a_failed_reader_stops_ingest() {
+        let path = std::env::temp_dir().join(format!("kv-for-likes-mixed-{}", std::process::id()));
+        std::fs::write(&path, "at://did:web:example.com/app.bsky.feed.post/1|x\n").unwrap();
+        let ran = run(2, path.to_str().unwrap(), || Ok(|_: &str| Err(anyhow!("database is locked"))), |reads| -> Result<()> {
+            loop {
+                reads.check()?;
+                thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+        std::fs::remove_file(&path).unwrap();
+        let e = format!("{:#}", ran.unwrap_err());
+        assert_eq!(e, "a reader failed: database is locked");
+    }
+}
@@ -0,0 +1,117 @@
+//! fixed-size pages for liker lists, so a hot subject's appends only touch
+//! its last page instead of rewriting one ever-growing value
+//!
+//! the subject key itself holds a [`Header`] with the like count and the number
+//! of pages, and page `n` lives at `<subject key>#<n as u32 be>`. pages sort
+//! after their header and in order, so a range over the subject streams them.
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+
+pub const HEADER_LEN: usize = 12;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub count: u64,
+    pub pages: u32,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..8].copy_from_slice(&self.count.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.pages.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Ok(bytes): std::result::Result<[u8; HEADER_LEN], _> = bytes.try_into() else {
+            return Err(anyhow!("page header must be {HEADER_LEN} bytes, found {}", bytes.len()))
+        };
+        Ok(Header {
+            count: u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes")),
+            pages: u32::from_be_bytes(bytes[8..].try_into().expect("4 bytes")),
+        })
+    }
+}
+
+/// Paging config: the number of likers per page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paging {
+    pub page_size: u64,
+}
+
+impl FromStr for Paging {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse()? {
+            0 => Err(anyhow!("page size must be at least 1")),
+            page_size => Ok(Paging { page_size }),
+        }
+    }
+}
+
+impl fmt::Display for Paging {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} likers/page", self.page_size)
+    }
+}
+
+impl Paging {
+    /// Place one more liker: the page it goes on, and the updated header
+    pub fn append(&self, header: Option<Header>) -> (u32, Header) {
+        let mut header = header.unwrap_or_default();
+        if header.count.is_multiple_of(self.page_size) {
+            header.pages += 1;
+        }
+        header.count += 1;
+        (header.pages - 1, header)
+    }
+}
+
+pub fn page_key(subject_key: &[u8], page: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(subject_key.len() + 5);
+    key.extend_from_slice(subject_key);
+    key.push(b'#');
+    key.extend_from_slice(&page.to_be_bytes());
+    key
+}
+
+/// The first and last page keys for a subject, for range reads
+pub fn page_range(subject_key: &[u8], header: &Header) -> (Vec<u8>, Vec<u8>) {
+    (page_key(subject_key, 0), page_key(subject_key, header.pages.saturating_sub(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = Header { count: 123_456, pages: 42 };
+        assert_eq!(Header::from_bytes(&header.to_bytes()).unwrap(), header);
+        assert!(Header::from_bytes(b"short").is_err());
+    }
+
+    #[test]
+    fn test_append_fills_pages() {
+        let paging = Paging { page_size: 3 };
+        let mut header = None;
+        let mut placed = vec![];
+        for _ in 0..7 {
+            let (page, next) = paging.append(header);
+            placed.push(page);
+            header = Some(next);
+        }
+        assert_eq!(placed, [0, 0, 0, 1, 1, 1, 2]);
+        assert_eq!(header, Some(Header { count: 7, pages: 3 }));
+    }
+
+    #[test]
+    fn test_page_keys_sort_after_header_in_order() {
+        let subject = b"at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l";
+        let mut keys = vec![page_key(subject, 300), page_key(subject, 2), page_key(subject, 0), subject.to_vec()];
+        keys.sort();
+        assert_eq!(keys, [subject.to_vec(), page_key(subject, 0), page_key(subject, 2), page_key(subject, 300)]);
+    }
+}
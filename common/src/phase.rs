@@ -0,0 +1,120 @@
+//! cold and warm passes for the read benchmarks
+//!
+//! every read run starts with one cold pass: the process drops the OS page
+//! cache (when it's allowed to) before opening the db, so neither the block
+//! cache nor the page cache has anything from ingest or an earlier run. modes
+//! that check against the db itself (has-liked and forward) read what they
+//! need through a throwaway open first. then come `--warm N` (default 2) warm
+//! passes on the same open handle. run the binary again for another cold pass.
+//!
+//! after each pass, backends that count block cache hits and misses print the
+//! counts for that pass.
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::process::Command;
+use anyhow::Result;
+
+use crate::cli;
+
+pub const DEFAULT_WARM_PASSES: usize = 2;
+
+const DROP_CACHES_PATH: &str = "/proc/sys/vm/drop_caches";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Cold,
+    Warm,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::Cold => write!(f, "cold"),
+            Phase::Warm => write!(f, "warm"),
+        }
+    }
+}
+
+/// Block cache hits and misses, since the db was opened
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheCounters {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Flush dirty pages and drop the OS page cache, so the next open reads from
+/// disk. needs root; prints what happened either way.
+pub fn drop_page_cache() {
+    let dropped = Command::new("sync")
+        .status()
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(fs::write(DROP_CACHES_PATH, "1")?));
+    match dropped {
+        Ok(()) => println!("page cache: dropped"),
+        Err(e) => println!("page cache: kept, so cold reads may still hit it ({e})"),
+    }
+}
+
+/// The passes to run, and how to read the backend's cache counters
+pub struct Phases<'a> {
+    warm: usize,
+    counters: Box<dyn Fn() -> Result<Option<CacheCounters>> + 'a>,
+    last: Cell<CacheCounters>,
+}
+
+impl<'a> Phases<'a> {
+    /// `--warm` passes after the cold one. `counters` reads the block cache
+    /// counters, or `None` if the backend doesn't keep any.
+    pub fn from_args(counters: impl Fn() -> Result<Option<CacheCounters>> + 'a) -> Result<Self> {
+        Ok(Phases {
+            warm: cli::opt("warm")?.unwrap_or(DEFAULT_WARM_PASSES),
+            counters: Box::new(counters),
+            last: Cell::new(CacheCounters::default()),
+        })
+    }
+
+    /// Every pass, numbered from 0
+    pub fn passes(&self) -> impl Iterator<Item = (usize, Phase)> {
+        std::iter::once(Phase::Cold)
+            .chain(std::iter::repeat_n(Phase::Warm, self.warm))
+            .enumerate()
+    }
+
+    /// Print the cache hits and misses since the last report, if the backend
+    /// counts them
+    pub fn report(&self) -> Result<()> {
+        let Some(now) = (self.counters)()? else {
+            return Ok(())
+        };
+        let last = self.last.replace(now);
+        let (hits, misses) = (now.hits - last.hits, now.misses - last.misses);
+        let rate = if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 };
+        println!("cache hits: {hits}, misses: {misses}, hit rate: {rate:.3}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_start_cold() {
+        let phases = Phases { warm: 2, counters: Box::new(|| Ok(None)), last: Cell::default() };
+        let passes: Vec<_> = phases.passes().collect();
+        assert_eq!(passes, [(0, Phase::Cold), (1, Phase::Warm), (2, Phase::Warm)]);
+        assert!(phases.report().is_ok());
+    }
+
+    #[test]
+    fn test_report_takes_deltas() {
+        let counts = Cell::new(CacheCounters { hits: 10, misses: 5 });
+        let phases = Phases { warm: 0, counters: Box::new(|| Ok(Some(counts.get()))), last: Cell::default() };
+        phases.report().unwrap();
+        assert_eq!(phases.last.get(), CacheCounters { hits: 10, misses: 5 });
+        counts.set(CacheCounters { hits: 25, misses: 6 });
+        phases.report().unwrap();
+        assert_eq!(phases.last.get(), CacheCounters { hits: 25, misses: 6 });
+    }
+}
@@ -0,0 +1,258 @@
+//! staged ingest: a reader thread, a pool of parsers, and the writer
+//!
+//! the reader cuts the input into numbered chunks of lines, `--parsers N`
+//! threads turn each chunk into whatever the backend writes (parsed entries
+//! with their keys), and the writer, on the calling thread, gets them back in
+//! chunk order. so entries are written in the order they were read, and a
+//! like always lands before its unlike. both channels are bounded, so a slow
+//! writer stalls the parsers and then the reader instead of buffering the
+//! input. each stage times its own work, and the run ends with each one's
+//! share of the wall time: a writer near 100% is waiting on the store.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::BufRead;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+
+use crate::cli;
+
+pub const DEFAULT_PARSERS: usize = 2;
+
+/// how many lines go to a parser at a time
+const CHUNK_LINES: usize = 256;
+
+/// how many chunks each channel holds per parser before its sender blocks
+const QUEUE_PER_PARSER: usize = 2;
+
+type Chunk<T> = (usize, Result<Vec<T>>);
+
+/// How long each stage spent working (not waiting on the others)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stages {
+    pub parsers: usize,
+    pub wall: Duration,
+    pub read: Duration,
+    /// summed over the parsers
+    pub parse: Duration,
+    pub write: Duration,
+}
+
+/// Each stage's share of the wall time, parsers as their average
+impl fmt::Display for Stages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = |d: Duration| 100.0 * d.as_secs_f64() / self.wall.as_secs_f64().max(f64::EPSILON);
+        write!(f, "stages over {:.1}s: read {:.1}%, parse {:.1}% (each of {}), write {:.1}%",
+            self.wall.as_secs_f32(),
+            share(self.read),
+            share(self.parse) / self.parsers as f64,
+            self.parsers,
+            share(self.write))
+    }
+}
+
+/// The parsed entries, in input order, for the writer
+pub struct Entries<T> {
+    parsed: Receiver<Chunk<T>>,
+    /// chunks that came back before the ones ahead of them
+    waiting: BTreeMap<usize, Result<Vec<T>>>,
+    next_chunk: usize,
+    current: std::vec::IntoIter<T>,
+    /// time spent blocked on the parsers
+    waited: Duration,
+}
+
+impl<T> Iterator for Entries<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if let Some(entry) = self.current.next() {
+            return Some(Ok(entry))
+        }
+        let t0 = Instant::now();
+        let chunk = loop {
+            if let Some(chunk) = self.waiting.remove(&self.next_chunk) {
+                break chunk
+            }
+            match self.parsed.recv() {
+                Ok((n, chunk)) => { self.waiting.insert(n, chunk); }
+                // every parser is done, and nothing is left
+                Err(_) => {
+                    self.waited += t0.elapsed();
+                    return None
+                }
+            }
+        };
+        self.waited += t0.elapsed();
+        self.next_chunk += 1;
+        match chunk {
+            Ok(entries) => {
+                self.current = entries.into_iter();
+                self.next()
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// `--parsers`, defaulting to `DEFAULT_PARSERS`
+pub fn parsers() -> Result<usize> {
+    match cli::opt("parsers")?.unwrap_or(DEFAULT_PARSERS) {
+        0 => Err(anyhow!("need at least one parser")),
+        n => Ok(n),
+    }
+}
+
+/// Read `input` on its own thread, `parse` each line on `parsers` threads, and
+/// hand the results to `write` in input order, then print the stage times.
+pub fn run<T, R>(
+    input: impl BufRead + Send,
+    parsers: usize,
+    parse: impl Fn(&str) -> Result<T> + Sync,
+    write: impl FnOnce(&mut Entries<T>) -> Result<R>,
+) -> Result<R>
+where
+    T: Send,
+{
+    let (written, stages) = run_staged(input, parsers, parse, write)?;
+    println!("{stages}");
+    Ok(written)
+}
+
+fn run_staged<T, R>(
+    input: impl BufRead + Send,
+    parsers: usize,
+    parse: impl Fn(&str) -> Result<T> + Sync,
+    write: impl FnOnce(&mut Entries<T>) -> Result<R>,
+) -> Result<(R, Stages)>
+where
+    T: Send,
+{
+    let t0 = Instant::now();
+    let (lines_tx, lines_rx) = sync_channel(parsers * QUEUE_PER_PARSER);
+    let (parsed_tx, parsed_rx) = sync_channel(parsers * QUEUE_PER_PARSER);
+    // only the parsers hold this, so the reader stops once they all have
+    let lines_rx = Arc::new(Mutex::new(lines_rx));
+
+    thread::scope(|s| {
+        let reader = s.spawn(move || read_chunks(input, lines_tx));
+        let parse_handles: Vec<_> = (0..parsers)
+            .map(|_| {
+                let (lines_rx, parsed_tx, parse) = (lines_rx.clone(), parsed_tx.clone(), &parse);
+                s.spawn(move || parse_chunks(lines_rx, parsed_tx, parse))
+            })
+            .collect();
+        drop(parsed_tx);
+        drop(lines_rx);
+
+        let mut entries = Entries {
+            parsed: parsed_rx,
+            waiting: BTreeMap::new(),
+            next_chunk: 0,
+            current: Vec::new().into_iter(),
+            waited: Duration::ZERO,
+        };
+        let written = write(&mut entries);
+        let waited = entries.waited;
+        // let the other stages see the writer is gone if it stopped early
+        drop(entries);
+
+        let read = reader.join().map_err(|_| anyhow!("the reader panicked"))?;
+        let mut parse = Duration::ZERO;
+        for handle in parse_handles {
+            parse += handle.join().map_err(|_| anyhow!("a parser panicked"))?;
+        }
+        let wall = t0.elapsed();
+        let stages = Stages { parsers, wall, read, parse, write: wall.saturating_sub(waited) };
+        Ok((written?, stages))
+    })
+}
+
+/// Send numbered chunks of lines until the input or the parsers run out, and
+/// say how long reading took
+fn read_chunks(input: impl BufRead, lines: SyncSender<Chunk<String>>) -> Duration {
+    let mut busy = Duration::ZERO;
+    let mut input = input.lines();
+    for n in 0.. {
+        let t0 = Instant::now();
+        let chunk: Result<Vec<_>> = input.by_ref().take(CHUNK_LINES).map(|l| Ok(l?)).collect();
+        busy += t0.elapsed();
+        let failed = chunk.is_err();
+        match chunk {
+            Ok(chunk) if chunk.is_empty() => break,
+            chunk => if lines.send((n, chunk)).is_err() || failed {
+                break
+            }
+        }
+    }
+    busy
+}
+
+/// Parse chunks until there are no more or the writer is gone, and say how
+/// long parsing took
+fn parse_chunks<T>(
+    lines: Arc<Mutex<Receiver<Chunk<String>>>>,
+    parsed: SyncSender<Chunk<T>>,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Duration {
+    let mut busy = Duration::ZERO;
+    loop {
+        let Ok((n, chunk)) = lines.lock().unwrap().recv() else {
+            return busy
+        };
+        let t0 = Instant::now();
+        let chunk = chunk.and_then(|lines| lines.iter().map(|line| parse(line)).collect());
+        busy += t0.elapsed();
+        if parsed.send((n, chunk)).is_err() {
+            return busy
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn numbers(n: usize) -> Cursor<String> {
+        Cursor::new((0..n).map(|i| format!("{i}\n")).collect())
+    }
+
+    #[test]
+    fn test_entries_come_back_in_order() {
+        let count = CHUNK_LINES * 20 + 7;
+        let (written, stages) = run_staged(numbers(count), 4, |line| {
+            let i: usize = line.parse()?;
+            // make later chunks finish first now and then
+            if i.is_multiple_of(CHUNK_LINES * 3) {
+                thread::sleep(Duration::from_millis(2));
+            }
+            Ok(i)
+        }, |entries| entries.collect::<Result<Vec<_>>>()).unwrap();
+        assert_eq!(written, (0..count).collect::<Vec<_>>());
+        assert_eq!(stages.parsers, 4);
+        assert!(stages.write <= stages.wall);
+    }
+
+    #[test]
+    fn test_parse_errors_reach_the_writer_in_place() {
+        let seen = run_staged(numbers(CHUNK_LINES * 4), 3, |line| {
+            match line {
+                "600" => Err(anyhow!("bad line")),
+                line => Ok(line.parse::<usize>()?),
+            }
+        }, |entries| Ok(entries.take_while(|e| e.is_ok()).count())).unwrap().0;
+        assert_eq!(seen, CHUNK_LINES * 2);
+    }
+
+    #[test]
+    fn test_a_writer_stopping_early_stops_the_rest() {
+        let stopped = run_staged(numbers(CHUNK_LINES * 100), 2, |line| Ok(line.to_string()), |entries| {
+            entries.next().transpose()?;
+            Err::<(), _>(anyhow!("store is full"))
+        });
+        assert_eq!(stopped.unwrap_err().to_string(), "store is full");
+    }
+}
@@ -0,0 +1,138 @@
+//! binary codec for atproto TID record keys
+//!
+//! a TID is 13 chars of base32-sortable encoding a 64-bit int (top bit always
+//! zero), so it packs into 8 big-endian bytes without changing its sort order.
+//!
+//! anything that isn't a valid TID falls back to raw bytes, marked with a
+//! leading `0xFF` (never the first byte of a packed TID) and terminated with a
+//! `0x00` (never found in an rkey). both forms are self-delimiting, so encoded
+//! rkeys can be embedded in the middle of keys and concatenated in values.
+//!
+//! TIDs sort among themselves and raw rkeys sort among themselves exactly as
+//! their strings would; all TIDs sort before all raw rkeys.
+use anyhow::{anyhow, Result};
+
+const ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+const TID_LEN: usize = 13;
+pub const PACKED_LEN: usize = 8;
+
+const RAW_MARKER: u8 = 0xFF;
+const RAW_END: u8 = 0x00;
+
+fn char_value(c: u8) -> Option<u64> {
+    match c {
+        b'2'..=b'7' => Some((c - b'2') as u64),
+        b'a'..=b'z' => Some((c - b'a') as u64 + 6),
+        _ => None,
+    }
+}
+
+/// Parse a TID string to its integer value, if it is one
+pub fn parse(rkey: &str) -> Option<u64> {
+    let bytes = rkey.as_bytes();
+    if bytes.len() != TID_LEN {
+        return None
+    }
+    // 13 chars is 65 bits: the first char only gets the top three bits of the
+    // u64, because the 64th bit must be zero (which also keeps clear of RAW_MARKER)
+    if char_value(bytes[0])? >= 8 {
+        return None
+    }
+    bytes.iter().try_fold(0u64, |n, c| Some((n << 5) | char_value(*c)?))
+}
+
+/// Format a TID integer value back to its 13-char string
+pub fn format(mut n: u64) -> String {
+    let mut out = [0u8; TID_LEN];
+    for c in out.iter_mut().rev() {
+        *c = ALPHABET[(n & 0x1f) as usize];
+        n >>= 5;
+    }
+    String::from_utf8(out.to_vec()).expect("alphabet is ascii")
+}
+
+/// Append the encoded form of an rkey
+pub fn encode_into(rkey: &str, out: &mut Vec<u8>) {
+    match parse(rkey) {
+        Some(n) => out.extend_from_slice(&n.to_be_bytes()),
+        None => {
+            out.push(RAW_MARKER);
+            out.extend_from_slice(rkey.as_bytes());
+            out.push(RAW_END);
+        }
+    }
+}
+
+pub fn encode(rkey: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(PACKED_LEN);
+    encode_into(rkey, &mut out);
+    out
+}
+
+/// Decode one rkey from the start of `bytes`, returning it with the number of
+/// bytes consumed
+pub fn decode(bytes: &[u8]) -> Result<(String, usize)> {
+    match bytes.first() {
+        None => Err(anyhow!("no bytes to decode an rkey from")),
+        Some(&RAW_MARKER) => {
+            let Some(end) = bytes.iter().position(|b| *b == RAW_END) else {
+                return Err(anyhow!("raw rkey is missing its terminator"))
+            };
+            let rkey = std::str::from_utf8(&bytes[1..end])?.to_string();
+            Ok((rkey, end + 1))
+        }
+        Some(_) => {
+            let Some(packed) = bytes.get(..PACKED_LEN) else {
+                return Err(anyhow!("packed tid needs {PACKED_LEN} bytes, found {}", bytes.len()))
+            };
+            let n = u64::from_be_bytes(packed.try_into().expect("slice is PACKED_LEN"));
+            Ok((format(n), PACKED_LEN))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tid_round_trip() {
+        for rkey in ["3ld53lnvvhc2w", "2222222222222", "bzzzzzzzzzzzz", "3lccjpbhjck2l"] {
+            let encoded = encode(rkey);
+            assert_eq!(encoded.len(), PACKED_LEN);
+            assert_eq!(decode(&encoded).unwrap(), (rkey.to_string(), PACKED_LEN));
+        }
+    }
+
+    #[test]
+    fn test_non_tid_round_trip() {
+        // wrong length, top bit set, bad charset, and empty
+        for rkey in ["self", "3ld53lnvvhc2", "czzzzzzzzzzzz", "3LD53LNVVHC2W", ""] {
+            let encoded = encode(rkey);
+            assert_eq!(encoded.len(), rkey.len() + 2);
+            assert_eq!(decode(&encoded).unwrap(), (rkey.to_string(), rkey.len() + 2));
+        }
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let mut rkeys = vec![
+            "3ld53lnvvhc2w", "2222222222222", "3lccjpbhjck2l", "bzzzzzzzzzzzz", "3ld53lnvvhc2v",
+            "self", "selfie", "abc", "3ld53lnvvhc2", "czzzzzzzzzzzz", "",
+        ];
+        let mut encoded: Vec<_> = rkeys.iter().map(|r| encode(r)).collect();
+        encoded.sort();
+        rkeys.sort_by_key(|r| (parse(r).is_none(), *r));
+        let decoded: Vec<_> = encoded.iter().map(|e| decode(e).unwrap().0).collect();
+        assert_eq!(decoded, rkeys);
+    }
+
+    #[test]
+    fn test_decode_embedded() {
+        let mut bytes = encode("self");
+        encode_into("3ld53lnvvhc2w", &mut bytes);
+        let (first, n) = decode(&bytes).unwrap();
+        assert_eq!(first, "self");
+        assert_eq!(decode(&bytes[n..]).unwrap().0, "3ld53lnvvhc2w");
+    }
+}
@@ -0,0 +1,160 @@
+//! checking reads against the samples without stopping at the first mismatch
+//!
+//! each read that doesn't match is recorded by kind, with a few examples, and
+//! the run keeps going. each pass prints how many reads it checked and its
+//! mismatches by kind, and the run fails at the end if there were any.
+use std::collections::BTreeMap;
+use std::fmt;
+use anyhow::{anyhow, Result};
+use crate::Encoding;
+
+/// how many mismatches of each kind are printed
+const MAX_EXAMPLES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// nothing came back
+    Missing,
+    /// a different number of entries (or a different count) came back
+    CountDiffers,
+    /// as many entries came back, but not the same ones
+    SetDiffers,
+    /// the same entries came back, in a different order
+    OrderDiffers,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Missing => write!(f, "missing"),
+            Kind::CountDiffers => write!(f, "count differs"),
+            Kind::SetDiffers => write!(f, "set differs"),
+            Kind::OrderDiffers => write!(f, "order differs"),
+        }
+    }
+}
+
+/// What's wrong with a list that came back, if anything, and how
+pub fn classify<T: Ord + Clone + fmt::Debug>(found: &[T], expected: &[T]) -> Option<(Kind, String)> {
+    if found == expected {
+        return None
+    }
+    if found.is_empty() {
+        return Some((Kind::Missing, format!("expected {}", expected.len())))
+    }
+    if found.len() != expected.len() {
+        return Some((Kind::CountDiffers, format!("found {}, expected {}", found.len(), expected.len())))
+    }
+    let (mut found_sorted, mut expected_sorted) = (found.to_vec(), expected.to_vec());
+    found_sorted.sort();
+    expected_sorted.sort();
+    if let Some((f, e)) = found_sorted.iter().zip(&expected_sorted).find(|(f, e)| f != e) {
+        return Some((Kind::SetDiffers, format!("found {f:?} where {e:?} was expected")))
+    }
+    let at = found.iter().zip(expected).position(|(f, e)| f != e).expect("the lists differ");
+    Some((Kind::OrderDiffers, format!("first out of order at {at}: {:?}", found[at])))
+}
+
+/// What's wrong with an encoded liker list that came back, if anything,
+/// against the sampled `did!rkey`s joined with `;`
+pub fn classify_likers(encoding: Encoding, found: Option<&[u8]>, expected: &str) -> Result<Option<(Kind, String)>> {
+    let found = found.map(|found| encoding.decode_likers(found)).transpose()?.unwrap_or_default();
+    let expected: Vec<_> = expected.split(';').map(|l| l.to_string()).collect();
+    Ok(classify(&found, &expected))
+}
+
+/// What's wrong with a count that came back, if anything
+pub fn classify_count(found: i64, expected: i64) -> Option<(Kind, String)> {
+    match (found, expected) {
+        (f, e) if f == e => None,
+        (0, e) => Some((Kind::Missing, format!("expected {e}"))),
+        (f, e) => Some((Kind::CountDiffers, format!("found {f}, expected {e}"))),
+    }
+}
+
+/// One pass's checked reads, and the ones that didn't match
+#[derive(Debug, Default)]
+pub struct Mismatches {
+    checked: u64,
+    kinds: BTreeMap<Kind, (u64, Vec<String>)>,
+}
+
+impl Mismatches {
+    /// Count a checked read of `what`, and its mismatch if it had one
+    pub fn record(&mut self, what: &str, mismatch: Option<(Kind, String)>) {
+        self.checked += 1;
+        let Some((kind, detail)) = mismatch else {
+            return
+        };
+        let (count, examples) = self.kinds.entry(kind).or_default();
+        *count += 1;
+        if examples.len() < MAX_EXAMPLES {
+            examples.push(format!("{what}: {detail}"));
+        }
+    }
+
+    /// How many reads didn't match
+    pub fn len(&self) -> u64 {
+        self.kinds.values().map(|(count, _)| count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    /// A line with the totals, then a line per kind with its count and examples
+    pub fn print(&self) {
+        println!("checked: {}, mismatched: {}", self.checked, self.len());
+        for (kind, (count, examples)) in &self.kinds {
+            println!("{kind}\t{count}");
+            for example in examples {
+                println!("  {example}");
+            }
+        }
+    }
+}
+
+/// An error if any pass had mismatches, for the end of the run
+pub fn outcome(mismatched: u64) -> Result<()> {
+    match mismatched {
+        0 => Ok(()),
+        n => Err(anyhow!("{n} reads didn't match the samples")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let expected = ["a", "b", "c"];
+        assert_eq!(classify(&["a", "b", "c"], &expected), None);
+        assert_eq!(classify::<&str>(&[], &expected).unwrap().0, Kind::Missing);
+        assert_eq!(classify(&["a", "b"], &expected).unwrap().0, Kind::CountDiffers);
+        assert_eq!(classify(&["a", "b", "d"], &expected).unwrap().0, Kind::SetDiffers);
+        assert_eq!(classify(&["a", "c", "b"], &expected).unwrap(), (Kind::OrderDiffers, "first out of order at 1: \"c\"".to_string()));
+        let likers = Encoding::Tid.join(None, [&Encoding::Tid.liker("did:web:example.com", "self")[..]]);
+        assert_eq!(classify_likers(Encoding::Tid, Some(&likers), "did:web:example.com!self").unwrap(), None);
+        assert_eq!(classify_likers(Encoding::Tid, None, "did:web:example.com!self").unwrap().unwrap().0, Kind::Missing);
+        assert_eq!(classify_count(3, 3), None);
+        assert_eq!(classify_count(0, 3).unwrap().0, Kind::Missing);
+        assert_eq!(classify_count(2, 3).unwrap().0, Kind::CountDiffers);
+    }
+
+    #[test]
+    fn test_mismatches_keep_a_few_examples() {
+        let mut mismatches = Mismatches::default();
+        for i in 0..10 {
+            mismatches.record(&format!("subject {i}"), (i % 2 == 0).then(|| (Kind::SetDiffers, "nope".to_string())));
+        }
+        mismatches.record("last", Some((Kind::Missing, "expected 1".to_string())));
+        assert_eq!(mismatches.checked, 11);
+        assert_eq!(mismatches.len(), 6);
+        let (count, examples) = &mismatches.kinds[&Kind::SetDiffers];
+        assert_eq!((*count, examples.len()), (5, MAX_EXAMPLES));
+        assert_eq!(examples[0], "subject 0: nope");
+        assert!(outcome(mismatches.len()).is_err());
+        assert!(outcome(Mismatches::default().len()).is_ok());
+    }
+}
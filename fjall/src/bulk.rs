@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use anyhow::{anyhow, Result};
+use fjall::{Config, PersistMode, PartitionCreateOptions};
+use kv_for_likes_common::{bulk, pipeline, Format};
+use kv_for_likes_common::bulk::{Entry, Record, Sort};
+use tikv_jemallocator::Jemalloc;
+
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+const DB_PATH: &str = "./likes.fjall";
+const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SORT_PATH: &str = "./likes.fjall.sort";
+
+/// records per batch: fjall 2.4 has no ingestion api, so sorted keys go in
+/// through big batches. they're still written to the journal, but not flushed
+/// or synced until the end
+const BATCH_RECORDS: usize = 10_000;
+
+fn main() -> Result<()> {
+    let reader = io::BufReader::new(File::open(LIKES_PATH)?);
+
+    let format = Format::from_args()?;
+    println!("{format}");
+    bulk::check(format)?;
+    if Path::new(DB_PATH).exists() {
+        return Err(anyhow!("bulk load makes a new db, move {DB_PATH} out of the way first"))
+    }
+    let sort = Sort::from_args(SORT_PATH)?;
+
+    let keyspace = Config::new(DB_PATH)
+        .max_write_buffer_size(160 * 2_u64.pow(20))
+        .manual_journal_persist(true)
+        .open()?;
+    let likes = keyspace.open_partition("likes", PartitionCreateOptions::default()
+        .max_memtable_size(64 * 2_u32.pow(20))
+        .block_size(32 * 2_u32.pow(10))
+        .manual_journal_persist(true))?;
+    let unlikes = keyspace.open_partition("unlikes", PartitionCreateOptions::default()
+        .max_memtable_size(16 * 2_u32.pow(20))
+        .block_size(16 * 2_u32.pow(10))
+        .manual_journal_persist(true))?;
+
+    let t0 = Instant::now();
+    let parsers = pipeline::parsers()?;
+    let loaded = pipeline::run(reader, parsers, |line| Entry::parse(format, line), |entries| {
+        let (mut batch, mut batched) = (keyspace.batch().durability(None), 0);
+        let loaded = bulk::load(entries, &sort, |key, likers| match format.paging {
+            Some(_) => bulk::liker_values(format, key, likers, Ok),
+            // unpaged likes are a key per like, like ingest writes them
+            None => Ok(likers.iter().map(|liker| ([key, b"!", liker].concat(), vec![])).collect()),
+        }, |record| {
+            match record {
+                Record::Likers { key, value } => batch.insert(&likes, key, value),
+                Record::Unlike(key) => batch.insert(&unlikes, key, ""),
+            }
+            batched += 1;
+            if batched == BATCH_RECORDS {
+                std::mem::replace(&mut batch, keyspace.batch().durability(None)).commit()?;
+                batched = 0;
+            }
+            Ok(())
+        })?;
+        batch.commit()?;
+        Ok(loaded)
+    })?;
+    keyspace.persist(PersistMode::SyncData)?;
+    // end with everything in segments, as close to ingested files as fjall gets
+    likes.rotate_memtable_and_wait()?;
+    unlikes.rotate_memtable_and_wait()?;
+
+    let d = t0.elapsed();
+    println!("done in {:.1}s. entries: {}, likes: {}, unlikes: {}, subjects: {}",
+        d.as_secs_f32(), loaded.likes + loaded.unlikes, loaded.likes, loaded.unlikes, loaded.subjects);
+    println!("sort runs: {}, size: {} ({} in segments)",
+        loaded.runs, keyspace.disk_space(), likes.disk_space() + unlikes.disk_space());
+    Ok(())
+}
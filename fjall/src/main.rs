@@ -1,9 +1,12 @@
 use std::fs::File;
-use std::io::{self, BufRead};
-use std::str::FromStr;
+use std::io;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use fjall::{Config, PersistMode, PartitionCreateOptions};
+use fjall::{Batch, Config, PersistMode, PartitionCreateOptions, PartitionHandle};
+use kv_for_likes_common::{counts, mixed, pipeline, Format};
+use kv_for_likes_common::durability::Durability;
+use kv_for_likes_common::mixed::Reads;
+use kv_for_likes_common::paging::{self, Header};
 use tikv_jemallocator::Jemalloc;
 use tinyjson::JsonValue;
 
@@ -12,9 +15,9 @@ static GLOBAL: Jemalloc = Jemalloc;
 
 const DB_PATH: &str = "./likes.fjall";
 const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
 
 const CHECKIN_STEP: u64 = 10_000;
-const SYNC_STEP: u64 = 100;
 
 
 #[derive(Debug, Default)]
@@ -36,18 +39,20 @@ struct CreateEntry {
     did: String,
     rkey: String,
     uri: String,
+    /// its subject key
+    key: Vec<u8>,
+    /// its `did!rkey`, as the format writes it
+    liker: Vec<u8>,
 }
 
 #[derive(Debug)]
 struct DeleteEntry {
-    did: String,
-    rkey: String,
+    liker: Vec<u8>,
 }
 
-impl FromStr for Action {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self> {
+impl Action {
+    /// Parse an entry and make its keys, which the parser threads do
+    fn parse(format: Format, s: &str) -> Result<Self> {
         let parsed: JsonValue = s.parse()?;
         let entry = <Vec<_>>::try_from(parsed)?;
         if entry.len() != 4 {
@@ -59,24 +64,57 @@ impl FromStr for Action {
         match action.as_str() {
             "c" => {
                 let uri = String::try_from(entry[3].clone())?;
-                Ok(Action::Create(CreateEntry { did, rkey, uri }))
+                let (key, liker) = (format.subject_key(&uri), format.liker(&did, &rkey));
+                Ok(Action::Create(CreateEntry { did, rkey, uri, key, liker }))
             }
             "d" => {
-                Ok(Action::Delete(DeleteEntry { did, rkey }))
+                Ok(Action::Delete(DeleteEntry { liker: format.liker(&did, &rkey) }))
             }
             _ => Err(anyhow!("need 'c' or 'd' for entry action type"))
         }
     }
 }
 
-fn show_update(d: Duration, size: u64, stats: &Stats) {
-    println!("{}\t{}\t{:.3}", stats.entries, size, d.as_secs_f32());
+fn show_update(d: Duration, size: u64, stats: &Stats, reads: &Reads) {
+    println!("{}\t{}\t{:.3}{}", stats.entries, size, d.as_secs_f32(), reads.checkin(stats.entries));
 }
 
+/// Fetch a subject's whole liker list like `read` does, for `--readers`
+fn read_likers(likes: &PartitionHandle, format: Format, uri: &str) -> Result<()> {
+    match format.paging {
+        None => {
+            for kv in likes.prefix(format.like_prefix(uri)) {
+                kv?;
+            }
+        }
+        Some(_) => {
+            let key = format.subject_key(uri);
+            if let Some(found) = likes.get(&key)? {
+                let header = Header::from_bytes(&found)?;
+                let (first, last) = paging::page_range(&key, &header);
+                for page in likes.range(first..=last) {
+                    page?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+/// Stage a change to a subject's maintained count in the batch
+fn add_count(batch: &mut Batch, subject_counts: &PartitionHandle, key: &[u8], delta: i64) -> Result<()> {
+    let count = subject_counts.get(key)?.map(|c| counts::from_bytes(&c)).transpose()?.unwrap_or(0);
+    batch.insert(subject_counts, key, counts::to_bytes(count + delta));
+    Ok(())
+}
 
 fn main() -> Result<()> {
     let reader = io::BufReader::new(File::open(LIKES_PATH)?);
 
+    let format = Format::from_args()?;
+    println!("{format}");
+
     let keyspace = Config::new(DB_PATH)
         .max_write_buffer_size(160 * 2_u64.pow(20))
         .manual_journal_persist(true)
@@ -93,34 +131,113 @@ fn main() -> Result<()> {
         .max_memtable_size(16 * 2_u32.pow(20))
         .block_size(16 * 2_u32.pow(10))
         .manual_journal_persist(true))?;
-
-    for line in reader.lines() {
-        let action: Action = line?.parse()?;
-        let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
-        let sync = (stats.entries % SYNC_STEP) == (SYNC_STEP - 1);
-
-        if sync {
-            keyspace.persist(PersistMode::SyncData)?;
-        }
-
-        match action {
-            Action::Create(entry) => {
-                let key = format!("{}!{}!{}", entry.uri, entry.did, entry.rkey);
-                likes.insert(&key, "")?;
-                stats.likes += 1;
-            }
-            Action::Delete(entry) => {
-                let key = format!("{}!{}", entry.did, entry.rkey);
-                unlikes.insert(&key, "")?;
-                stats.unlikes += 1;
+    let listing = format.listing.then(|| keyspace.open_partition("listing", PartitionCreateOptions::default()
+        .max_memtable_size(64 * 2_u32.pow(20))
+        .block_size(32 * 2_u32.pow(10))
+        .manual_journal_persist(true))).transpose()?;
+    // unpaged likes are already a key per like, so only paged ones need the index
+    let liked = (format.liked && format.paging.is_some()).then(|| keyspace.open_partition("liked", PartitionCreateOptions::default()
+        .max_memtable_size(64 * 2_u32.pow(20))
+        .block_size(32 * 2_u32.pow(10))
+        .manual_journal_persist(true))).transpose()?;
+    let forward = format.forward_index.then(|| keyspace.open_partition("forward", PartitionCreateOptions::default()
+        .max_memtable_size(64 * 2_u32.pow(20))
+        .block_size(32 * 2_u32.pow(10))
+        .manual_journal_persist(true))).transpose()?;
+    let subject_counts = format.counts.then(|| keyspace.open_partition("counts", PartitionCreateOptions::default()
+        .max_memtable_size(16 * 2_u32.pow(20))
+        .block_size(16 * 2_u32.pow(10))
+        .manual_journal_persist(true))).transpose()?;
+    let like_subjects = format.counts.then(|| keyspace.open_partition("like_subjects", PartitionCreateOptions::default()
+        .max_memtable_size(32 * 2_u32.pow(20))
+        .block_size(16 * 2_u32.pow(10))
+        .manual_journal_persist(true))).transpose()?;
+
+    let durability = Durability::from_args()?;
+    println!("durability: {durability}");
+    // the journal is only written out when a batch asks, so a periodic sync is
+    // a persist on top of buffered batches
+    let persist = match durability {
+        Durability::None => None,
+        Durability::Sync => Some(PersistMode::SyncData),
+        _ => Some(PersistMode::Buffer),
+    };
+
+    let readers = mixed::readers()?;
+    let parsers = pipeline::parsers()?;
+    mixed::run(readers, SUBJECTS_PATH, || Ok(|uri: &str| read_likers(&likes, format, uri)), |reads| {
+        pipeline::run(reader, parsers, |line| Action::parse(format, line), |actions| {
+            let mut syncs = durability.syncs();
+            for action in actions {
+                let action = action?;
+                let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
+                let sync = syncs.due();
+
+                match action {
+                    Action::Create(entry) => {
+                        let mut batch = keyspace.batch().durability(persist);
+                        if let Some(paging) = format.paging {
+                            let key = &entry.key;
+                            let header = likes.get(key)?.map(|h| Header::from_bytes(&h)).transpose()?;
+                            if header.is_none() {
+                                stats.subjects += 1;
+                            }
+                            let (page, header) = paging.append(header);
+                            batch.insert(&likes, key, header.to_bytes());
+                            let page_key = paging::page_key(key, page);
+                            let page = format.encoding.join(likes.get(&page_key)?.as_deref(), [&*entry.liker]);
+                            batch.insert(&likes, &page_key, page);
+                        } else {
+                            let key = format.like_key(&entry.uri, &entry.did, &entry.rkey);
+                            batch.insert(&likes, &key, "");
+                        }
+                        if let Some(liked) = &liked {
+                            batch.insert(liked, format.like_key(&entry.uri, &entry.did, &entry.rkey), "");
+                        }
+                        if let Some(forward) = &forward {
+                            batch.insert(forward, &entry.liker, &entry.uri);
+                        }
+                        if let Some(listing) = &listing {
+                            batch.insert(listing, format.listing_key(&entry.uri, &entry.did, &entry.rkey), "");
+                        }
+                        if let (Some(subject_counts), Some(like_subjects)) = (&subject_counts, &like_subjects) {
+                            add_count(&mut batch, subject_counts, &entry.key, 1)?;
+                            batch.insert(like_subjects, &entry.liker, &entry.key);
+                        }
+                        batch.commit()?;
+                        stats.likes += 1;
+                    }
+                    Action::Delete(entry) => {
+                        let key = entry.liker;
+                        let mut batch = keyspace.batch().durability(persist);
+                        batch.insert(&unlikes, &key, "");
+                        if let Some(forward) = &forward {
+                            batch.remove(forward, &key);
+                        }
+                        if let (Some(subject_counts), Some(like_subjects)) = (&subject_counts, &like_subjects) {
+                            // likes from before the data started have no subject to take from
+                            if let Some(subject) = like_subjects.get(&key)? {
+                                add_count(&mut batch, subject_counts, &subject, -1)?;
+                                batch.remove(like_subjects, &key);
+                            }
+                        }
+                        batch.commit()?;
+                        stats.unlikes += 1;
+                    }
+                }
+                if sync {
+                    keyspace.persist(PersistMode::SyncData)?;
+                }
+                stats.entries += 1;
+
+                if checkin {
+                    reads.check()?;
+                    show_update(t0.elapsed(), keyspace.disk_space(), &stats, reads);
+                }
             }
-        }
-        stats.entries += 1;
-
-        if checkin {
-            show_update(t0.elapsed(), keyspace.disk_space(), &stats);
-        }
-    }
+            Ok(())
+        })
+    })?;
 
     keyspace.persist(PersistMode::SyncData)?;
 
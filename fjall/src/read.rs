@@ -1,10 +1,17 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use fjall::{Config, PartitionCreateOptions, BlockCache};
+use fjall::{Config, PartitionCreateOptions, PartitionHandle, BlockCache};
+use kv_for_likes_common::{authority, bench, cli, counts, forward, Encoding, Format};
+use kv_for_likes_common::bench::{Export, Mode};
+use kv_for_likes_common::histogram::Timings;
+use kv_for_likes_common::listing::{self, Cursor, Order, Page};
+use kv_for_likes_common::paging::{self, Header};
+use kv_for_likes_common::phase::{self, Phases};
+use kv_for_likes_common::verify::{self, Mismatches};
 
 const DB_PATH: &str = "./likes.fjall";
 const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
@@ -26,45 +33,227 @@ impl FromStr for Subject {
     }
 }
 
+/// A paged subject's whole liker list, streaming its pages in order
+fn get_paged_likers(likes: &PartitionHandle, encoding: Encoding, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(found) = likes.get(key)? else {
+        return Ok(None)
+    };
+    let header = Header::from_bytes(&found)?;
+    let (first, last) = paging::page_range(key, &header);
+    let mut pages = vec![];
+    for page in likes.range(first..=last) {
+        pages.push(page?.1);
+    }
+    Ok(Some(encoding.join(None, pages.iter().map(|p| &p[..]))))
+}
+
+/// Each subject's whole liker list, all read from one snapshot
+fn get_batch(likes: &PartitionHandle, format: Format, uris: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+    let snapshot = likes.snapshot();
+    let mut found = vec![];
+    for uri in uris {
+        let likers = match format.paging {
+            None => {
+                let prefix = format.like_prefix(uri);
+                let mut likers = vec![];
+                for kv in snapshot.prefix(&prefix) {
+                    likers.push(kv?.0);
+                }
+                (!likers.is_empty()).then(|| format.encoding.join(None, likers.iter().map(|k| &k[prefix.len()..])))
+            }
+            Some(_) => {
+                let key = format.subject_key(uri);
+                match snapshot.get(&key)? {
+                    None => None,
+                    Some(found) => {
+                        let header = Header::from_bytes(&found)?;
+                        let (first, last) = paging::page_range(&key, &header);
+                        let mut pages = vec![];
+                        for page in snapshot.range(first..=last) {
+                            pages.push(page?.1);
+                        }
+                        Some(format.encoding.join(None, pages.iter().map(|p| &p[..])))
+                    }
+                }
+            }
+        };
+        found.push(likers);
+    }
+    Ok(found)
+}
+
+/// Every like on the records of a did, streamed from a prefix scan
+fn likes_for_authority<'a>(
+    likes: &'a PartitionHandle,
+    format: Format,
+    did: &str,
+    collection: Option<&'a str>,
+) -> Result<impl Iterator<Item = Result<authority::Like>> + 'a> {
+    let prefix = authority::prefix(format, did, collection)?;
+    let rows = likes.prefix(prefix.clone()).map(|kv| {
+        let (k, v) = kv?;
+        Ok((k.to_vec(), v.to_vec()))
+    });
+    Ok(authority::likes(format, prefix, collection, rows, Ok))
+}
+
+/// One page of a subject's likers from the listing partition
+fn list_likers(listing: &PartitionHandle, key: &[u8], order: Order, limit: usize, cursor: Option<&Cursor>) -> Result<Page> {
+    let prefix = listing::prefix(key);
+    let start = listing::start_key(&prefix, order, cursor);
+    let key_of = |kv: fjall::Result<(fjall::Slice, fjall::Slice)>| -> Result<Vec<u8>> { Ok(kv?.0.to_vec()) };
+    match order {
+        Order::OldestFirst => listing::page_from_keys(&prefix, cursor, limit, listing.range(start..).map(key_of)),
+        Order::NewestFirst => listing::page_from_keys(&prefix, cursor, limit, listing.range(..start).rev().map(key_of)),
+    }
+}
+
+/// One page of a did's likes from the forward partition
+fn list_liked(forward: &PartitionHandle, encoding: Encoding, did: &str, order: Order, limit: usize, cursor: Option<&Cursor>) -> Result<forward::Page> {
+    let prefix = encoding.liker_prefix(did);
+    let start = listing::start_key(&prefix, order, cursor);
+    let kv_of = |kv: fjall::Result<(fjall::Slice, fjall::Slice)>| -> Result<(Vec<u8>, Vec<u8>)> {
+        let (k, v) = kv?;
+        Ok((k.to_vec(), v.to_vec()))
+    };
+    match order {
+        Order::OldestFirst => forward::page_from_kvs(encoding, &prefix, cursor, limit, forward.range(start..).map(kv_of)),
+        Order::NewestFirst => forward::page_from_kvs(encoding, &prefix, cursor, limit, forward.range(..start).rev().map(kv_of)),
+    }
+}
+
+/// Whether a did has a like on a subject that it hasn't unliked
+fn has_liked(index: &PartitionHandle, unlikes: &PartitionHandle, format: Format, uri: &str, did: &str) -> Result<bool> {
+    let liker_at = format.like_prefix(uri).len();
+    for kv in index.prefix(format.liked_prefix(uri, did)) {
+        let (key, _) = kv?;
+        if !unlikes.contains_key(&key[liker_at..])? {
+            return Ok(true)
+        }
+    }
+    Ok(false)
+}
+
 fn main() -> Result<()> {
+    let format = Format::from_args()?;
+    println!("{format}");
+
+    let mode: Mode = cli::opt_or_default("mode")?;
+    println!("mode: {mode}");
+    let sampled_unlikes = match mode {
+        Mode::Forward | Mode::HasLiked => {
+            // from a throwaway open, so the timed one starts cold
+            let keyspace = Config::new(DB_PATH).open()?;
+            let unlikes = keyspace.open_partition("unlikes", PartitionCreateOptions::default().block_size(16 * 2_u32.pow(10)))?;
+            bench::sampled_unlikes(SUBJECTS_PATH, format, |liker| Ok(unlikes.contains_key(liker)?))?
+        }
+        _ => HashSet::new(),
+    };
+
+    phase::drop_page_cache();
     let keyspace = Config::new(DB_PATH)
         .block_cache(BlockCache::with_capacity_bytes(64 * 2_u64.pow(20)).into())
         .open()?;
 
     let likes = keyspace.open_partition("likes", PartitionCreateOptions::default().block_size(32 * 2_u32.pow(10)))?;
 
-    println!("loop\tduration");
-    for n in 0..=2 {
+    // fjall doesn't count block cache hits
+    let phases = Phases::from_args(|| Ok(None))?;
+
+    if mode == Mode::List {
+        let listing = keyspace.open_partition("listing", PartitionCreateOptions::default().block_size(32 * 2_u32.pow(10)))?;
+        return bench::run_list(SUBJECTS_PATH, format, &phases, |key, order, limit, cursor| {
+            list_likers(&listing, key, order, limit, cursor)
+        })
+    }
+    if mode == Mode::Forward {
+        let forward = keyspace.open_partition("forward", PartitionCreateOptions::default().block_size(32 * 2_u32.pow(10)))?;
+        return bench::run_forward(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |did, order, limit, cursor| list_liked(&forward, format.encoding, did, order, limit, cursor),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::HasLiked {
+        let unlikes = keyspace.open_partition("unlikes", PartitionCreateOptions::default().block_size(16 * 2_u32.pow(10)))?;
+        let index = match format.paging {
+            None => likes.clone(),
+            Some(_) if format.liked => keyspace.open_partition("liked", PartitionCreateOptions::default().block_size(32 * 2_u32.pow(10)))?,
+            Some(_) => return Err(anyhow!("has-liked on paged likes needs a db ingested with --liked (and --liked here too)")),
+        };
+        return bench::run_has_liked(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |uri, did| has_liked(&index, &unlikes, format, uri, did),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::Batch {
+        return bench::run_batch(SUBJECTS_PATH, format, &phases, |uris| get_batch(&likes, format, uris))
+    }
+    if mode == Mode::Authority {
+        return bench::run_authority(SUBJECTS_PATH, format, &phases, |did, collection| {
+            likes_for_authority(&likes, format, did, collection)?.collect()
+        })
+    }
+    if mode == Mode::Count {
+        let subject_counts = keyspace.open_partition("counts", PartitionCreateOptions::default().block_size(16 * 2_u32.pow(10)))?;
+        let unlikes = keyspace.open_partition("unlikes", PartitionCreateOptions::default().block_size(16 * 2_u32.pow(10)))?;
+        return bench::run_count(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |key| Ok(subject_counts.get(key)?.map(|c| counts::from_bytes(&c)).transpose()?.unwrap_or(0)),
+            |liker| Ok(unlikes.contains_key(liker)?),
+        )
+    }
+
+    let mut export = Export::from_args(Mode::Full)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
         let reader = io::BufReader::new(File::open(SUBJECTS_PATH)?);
+        let mut mismatches = Mismatches::default();
 
         let mut total = Duration::from_secs(0);
-        let mut times: HashMap<usize, Vec<f64>> = HashMap::new();
+        let mut times = Timings::default();
 
         for line in reader.lines() {
             let subject: Subject = line?.parse()?;
             let n_likes = subject.likers.split(';').count();
 
-            let t0 = Instant::now();
-            let db_n_likes = likes.prefix(&subject.uri).count();
-            let d = t0.elapsed();
+            // unpaged likes are only counted, like they always have been
+            let (d, mismatch) = match format.paging {
+                None => {
+                    let prefix = format.like_prefix(&subject.uri);
+                    let t0 = Instant::now();
+                    let db_n_likes = likes.prefix(&prefix).count();
+                    (t0.elapsed(), verify::classify_count(db_n_likes as i64, n_likes as i64))
+                }
+                Some(_) => {
+                    let key = format.subject_key(&subject.uri);
+                    let t0 = Instant::now();
+                    let db_likers = get_paged_likers(&likes, format.encoding, &key)?;
+                    let d = t0.elapsed();
+                    (d, verify::classify_likers(format.encoding, db_likers.as_deref(), &subject.likers)?)
+                }
+            };
 
             total += d;
-            (*times.entry(n_likes).or_insert(vec![])).push(d.as_nanos() as f64);
+            times.record(n_likes, d);
 
-            assert_eq!(db_n_likes, n_likes);
-        }
-        println!("{n}\t{:.3}", total.as_secs_f32());
-
-        let mut res: Vec<_> = times
-            .iter()
-            .map(|(likes, group)|
-                (likes, group.into_iter().sum::<f64>() / (group.len() as f64) / 1000.0))
-            .collect();
-        res.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        for (likes, micros) in res {
-            println!("{likes}\t{micros:.3}");
+            mismatches.record(&subject.uri, mismatch);
         }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
     }
 
-    Ok(())
+    export.write()?;
+    verify::outcome(mismatched)
 }
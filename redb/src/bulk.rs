@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use anyhow::{anyhow, Result};
+use kv_for_likes_common::{bulk, pipeline, Format};
+use kv_for_likes_common::bulk::{Entry, Record, Sort};
+use kv_for_likes_common::dict::ValueCodec;
+use redb::{Database, TableDefinition};
+
+const DB_PATH: &str = "./likes.redb";
+const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SORT_PATH: &str = "./likes.redb.sort";
+
+const LIKES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("likes");
+const UNLIKES: TableDefinition<&[u8], ()> = TableDefinition::new("unlikes");
+
+fn main() -> Result<()> {
+    let reader = io::BufReader::new(File::open(LIKES_PATH)?);
+
+    let format = Format::from_args()?;
+    println!("{format}");
+    bulk::check(format)?;
+    let mut codec = ValueCodec::from_args()?;
+    if let Some(codec) = &codec {
+        println!("values: {codec}");
+    }
+    if Path::new(DB_PATH).exists() {
+        return Err(anyhow!("bulk load makes a new db, move {DB_PATH} out of the way first"))
+    }
+    let sort = Sort::from_args(SORT_PATH)?;
+
+    let db = Database::create(DB_PATH)?;
+
+    let t0 = Instant::now();
+    // everything goes in one transaction, with one sync at its commit
+    let tx = db.begin_write()?;
+    let parsers = pipeline::parsers()?;
+    let loaded = {
+        let mut likes = tx.open_table(LIKES)?;
+        let mut unlikes = tx.open_table(UNLIKES)?;
+        pipeline::run(reader, parsers, |line| Entry::parse(format, line), |entries| {
+            bulk::load(entries, &sort, |key, likers| bulk::liker_values(format, key, likers, |v| match codec.as_mut() {
+                Some(codec) => codec.compress(&v),
+                None => Ok(v),
+            }), |record| {
+                match record {
+                    Record::Likers { key, value } => { likes.insert(key, value)?; }
+                    Record::Unlike(key) => { unlikes.insert(key, ())?; }
+                }
+                Ok(())
+            })
+        })?
+    };
+    let size = {
+        let stats = tx.stats()?;
+        stats.stored_bytes() + stats.metadata_bytes() + stats.fragmented_bytes()
+    };
+    tx.commit()?;
+
+    let d = t0.elapsed();
+    println!("done in {:.1}s. entries: {}, likes: {}, unlikes: {}, subjects: {}",
+        d.as_secs_f32(), loaded.likes + loaded.unlikes, loaded.likes, loaded.unlikes, loaded.subjects);
+    println!("sort runs: {}, size: {size}", loaded.runs);
+
+    Ok(())
+}
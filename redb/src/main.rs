@@ -1,19 +1,30 @@
 use std::fs::File;
-use std::io::{self, BufRead};
-use std::str::FromStr;
+use std::io;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use redb::{Database, TableDefinition, WriteTransaction, ReadableTable, DatabaseStats};
+use kv_for_likes_common::{mixed, pipeline, Format};
+use kv_for_likes_common::dict::ValueCodec;
+use kv_for_likes_common::durability::Durability;
+use kv_for_likes_common::mixed::Reads;
+use kv_for_likes_common::paging::{self, Header};
+use redb::{Database, TableDefinition, TableError, WriteTransaction, ReadableTable, DatabaseStats};
 use tinyjson::JsonValue;
 
 const DB_PATH: &str = "./likes.redb";
 const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
 
 const CHECKIN_STEP: u64 = 10_000;
-const SYNC_STEP: u64 = 100;
+/// how many entries go in each transaction with `--durability none`
+const UNSYNCED_COMMIT_STEP: u64 = 100;
 
-const LIKES: TableDefinition<&str, &str> = TableDefinition::new("likes");
-const UNLIKES: TableDefinition<&str, ()> = TableDefinition::new("unlikes");
+const LIKES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("likes");
+const UNLIKES: TableDefinition<&[u8], ()> = TableDefinition::new("unlikes");
+const LISTING: TableDefinition<&[u8], ()> = TableDefinition::new("listing");
+const LIKED: TableDefinition<(&[u8], &[u8]), ()> = TableDefinition::new("liked");
+const FORWARD: TableDefinition<&[u8], &str> = TableDefinition::new("forward");
+const COUNTS: TableDefinition<&[u8], i64> = TableDefinition::new("counts");
+const LIKE_SUBJECTS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("like_subjects");
 
 #[derive(Debug, Default)]
 struct Stats {
@@ -34,18 +45,20 @@ struct CreateEntry {
     did: String,
     rkey: String,
     uri: String,
+    /// its subject key
+    key: Vec<u8>,
+    /// its `did!rkey`, as the format writes it
+    liker: Vec<u8>,
 }
 
 #[derive(Debug)]
 struct DeleteEntry {
-    did: String,
-    rkey: String,
+    liker: Vec<u8>,
 }
 
-impl FromStr for Action {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self> {
+impl Action {
+    /// Parse an entry and make its keys, which the parser threads do
+    fn parse(format: Format, s: &str) -> Result<Self> {
         let parsed: JsonValue = s.parse()?;
         let entry = <Vec<_>>::try_from(parsed)?;
         if entry.len() != 4 {
@@ -57,10 +70,11 @@ impl FromStr for Action {
         match action.as_str() {
             "c" => {
                 let uri = String::try_from(entry[3].clone())?;
-                Ok(Action::Create(CreateEntry { did, rkey, uri }))
+                let (key, liker) = (format.subject_key(&uri), format.liker(&did, &rkey));
+                Ok(Action::Create(CreateEntry { did, rkey, uri, key, liker }))
             }
             "d" => {
-                Ok(Action::Delete(DeleteEntry { did, rkey }))
+                Ok(Action::Delete(DeleteEntry { liker: format.liker(&did, &rkey) }))
             }
             _ => Err(anyhow!("need 'c' or 'd' for entry action type"))
         }
@@ -68,34 +82,124 @@ impl FromStr for Action {
 }
 
 
-fn persist_like(tx: &WriteTransaction, action: CreateEntry, stats: &mut Stats) -> Result<()> {
-    let mut val = format!("{}!{}", action.did, action.rkey);
+/// Change a subject's maintained count, in the like's transaction
+fn add_count(tx: &WriteTransaction, key: &[u8], delta: i64) -> Result<()> {
+    let mut counts = tx.open_table(COUNTS)?;
+    let count = counts.get(key)?.map(|c| c.value()).unwrap_or(0);
+    counts.insert(key, count + delta)?;
+    Ok(())
+}
+
+fn persist_like(
+    tx: &WriteTransaction,
+    format: Format,
+    codec: Option<&mut ValueCodec>,
+    action: CreateEntry,
+    stats: &mut Stats,
+) -> Result<()> {
+    let mut key = action.key.clone();
+    let mut val = action.liker.clone();
     let mut table = tx.open_table(LIKES)?;
-    if let Some(existing) = table.get(&*action.uri)? {
-        val = format!("{};{}", existing.value(), val);
-    } else {
-        stats.subjects += 1;
+    if let Some(paging) = format.paging {
+        let header = table.get(&*key)?.map(|h| Header::from_bytes(h.value())).transpose()?;
+        if header.is_none() {
+            stats.subjects += 1;
+        }
+        let (page, header) = paging.append(header);
+        table.insert(&*key, &header.to_bytes()[..])?;
+        key = paging::page_key(&key, page);
+    }
+    let existing = table.get(&*key)?.map(|v| v.value().to_vec());
+    match (existing, codec) {
+        (Some(existing), Some(codec)) => {
+            let existing = codec.decompress(&existing)?;
+            val = codec.compress(&format.encoding.join(Some(&existing), [&*val]))?;
+        }
+        (Some(existing), None) => val = format.encoding.join(Some(&existing), [&*val]),
+        (None, codec) => {
+            if format.paging.is_none() {
+                stats.subjects += 1;
+            }
+            if let Some(codec) = codec {
+                val = codec.compress(&val)?;
+            }
+        }
+    }
+    table.insert(&*key, &*val)?;
+    if format.listing {
+        tx.open_table(LISTING)?.insert(&*format.listing_key(&action.uri, &action.did, &action.rkey), ())?;
+    }
+    if format.forward_index {
+        tx.open_table(FORWARD)?.insert(&*action.liker, &*action.uri)?;
+    }
+    if format.liked {
+        tx.open_table(LIKED)?.insert((&*action.key, &*action.liker), ())?;
+    }
+    if format.counts {
+        add_count(tx, &action.key, 1)?;
+        tx.open_table(LIKE_SUBJECTS)?.insert(&*action.liker, &*action.key)?;
     }
-    table.insert(&*action.uri, &*val)?;
     stats.likes += 1;
     Ok(())
 }
 
-fn persist_unlike(tx: &WriteTransaction, action: DeleteEntry, stats: &mut Stats) -> Result<()> {
-    let key = format!("{}!{}", action.did, action.rkey);
+fn persist_unlike(tx: &WriteTransaction, format: Format, action: DeleteEntry, stats: &mut Stats) -> Result<()> {
+    let key = action.liker;
     tx.open_table(UNLIKES)?.insert(&*key, ())?;
+    if format.forward_index {
+        tx.open_table(FORWARD)?.remove(&*key)?;
+    }
+    if format.counts {
+        // likes from before the data started have no subject to take from
+        let subject = tx.open_table(LIKE_SUBJECTS)?.remove(&*key)?.map(|s| s.value().to_vec());
+        if let Some(subject) = subject {
+            add_count(tx, &subject, -1)?;
+        }
+    }
     stats.unlikes += 1;
     Ok(())
 }
 
-fn show_update(d: Duration, db_stats: DatabaseStats, stats: &Stats) {
+fn show_update(d: Duration, db_stats: DatabaseStats, stats: &Stats, reads: &Reads) {
     let total_size = db_stats.stored_bytes() + db_stats.metadata_bytes() + db_stats.fragmented_bytes();
-    println!("{}\t{}\t{:.3}", stats.entries, total_size, d.as_secs_f32());
+    println!("{}\t{}\t{:.3}{}", stats.entries, total_size, d.as_secs_f32(), reads.checkin(stats.entries));
+}
+
+/// Fetch a subject's whole liker list like `read` does, in its own read
+/// transaction, for `--readers`
+fn read_likers(db: &Database, format: Format, uri: &str) -> Result<()> {
+    let likes = match db.begin_read()?.open_table(LIKES) {
+        Ok(likes) => likes,
+        // nothing's been committed yet
+        Err(TableError::TableDoesNotExist(_)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let key = format.subject_key(uri);
+    let Some(found) = likes.get(&*key)? else {
+        return Ok(())
+    };
+    if format.paging.is_some() {
+        let header = Header::from_bytes(found.value())?;
+        let (first, last) = paging::page_range(&key, &header);
+        for page in likes.range(&*first..=&*last)? {
+            page?;
+        }
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let reader = io::BufReader::new(File::open(LIKES_PATH)?);
 
+    let format = Format::from_args()?;
+    println!("{format}");
+    let mut codec = ValueCodec::from_args()?;
+    if let Some(codec) = &codec {
+        println!("values: {codec}");
+    }
+    let durability = Durability::from_args()?;
+    println!("durability: {durability}");
+
     let db = Database::create(DB_PATH)?;
 
     let mut stats: Stats = Default::default();
@@ -103,26 +207,46 @@ fn main() -> Result<()> {
 
     let mut tx = db.begin_write()?;
 
-    for line in reader.lines() {
-        let action: Action = line?.parse()?;
-        let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
-        let sync = (stats.entries % SYNC_STEP) == (SYNC_STEP - 1);
-
-        if sync {
-            tx.commit()?;
-            tx = db.begin_write()?;
-        }
+    let readers = mixed::readers()?;
+    let parsers = pipeline::parsers()?;
+    let tx = mixed::run(readers, SUBJECTS_PATH, || Ok(|uri: &str| read_likers(&db, format, uri)), |reads| {
+        pipeline::run(reader, parsers, |line| Action::parse(format, line), |actions| {
+            let mut syncs = durability.syncs();
+            for action in actions {
+                let action = action?;
+                let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
+                let sync = syncs.due();
 
-        match action {
-            Action::Create(entry) => persist_like(&tx, entry, &mut stats)?,
-            Action::Delete(entry) => persist_unlike(&tx, entry, &mut stats)?,
-        }
-        stats.entries += 1;
+                match action {
+                    Action::Create(entry) => persist_like(&tx, format, codec.as_mut(), entry, &mut stats)?,
+                    Action::Delete(entry) => persist_unlike(&tx, format, entry, &mut stats)?,
+                }
+                // every entry but `none`'s is its own commit, written out
+                // without an fsync unless it's synced
+                let commit = match durability {
+                    Durability::None => (stats.entries % UNSYNCED_COMMIT_STEP) == (UNSYNCED_COMMIT_STEP - 1),
+                    _ => true,
+                };
+                if commit {
+                    tx.set_durability(match durability {
+                        Durability::None => redb::Durability::None,
+                        Durability::Sync => redb::Durability::Immediate,
+                        _ if sync => redb::Durability::Immediate,
+                        _ => redb::Durability::Eventual,
+                    });
+                    tx.commit()?;
+                    tx = db.begin_write()?;
+                }
+                stats.entries += 1;
 
-        if checkin {
-            show_update(t0.elapsed(), tx.stats()?, &stats);
-        }
-    }
+                if checkin {
+                    reads.check()?;
+                    show_update(t0.elapsed(), tx.stats()?, &stats, reads);
+                }
+            }
+            Ok(tx)
+        })
+    })?;
 
     tx.commit()?;
 
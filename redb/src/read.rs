@@ -1,15 +1,28 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use redb::{Database, TableDefinition};
+use kv_for_likes_common::{authority, bench, cli, forward, Encoding, Format};
+use kv_for_likes_common::bench::{Export, Mode};
+use kv_for_likes_common::histogram::Timings;
+use kv_for_likes_common::dict::ValueCodec;
+use kv_for_likes_common::listing::{self, Cursor, Order, Page};
+use kv_for_likes_common::paging::{self, Header};
+use kv_for_likes_common::phase::{self, Phases};
+use kv_for_likes_common::verify::{self, Mismatches};
+use redb::{AccessGuard, Database, ReadOnlyTable, StorageError, TableDefinition};
 
 const DB_PATH: &str = "./likes.redb";
 const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
 
-const LIKES: TableDefinition<&str, &str> = TableDefinition::new("likes");
+const LIKES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("likes");
+const LISTING: TableDefinition<&[u8], ()> = TableDefinition::new("listing");
+const LIKED: TableDefinition<(&[u8], &[u8]), ()> = TableDefinition::new("liked");
+const FORWARD: TableDefinition<&[u8], &str> = TableDefinition::new("forward");
+const COUNTS: TableDefinition<&[u8], i64> = TableDefinition::new("counts");
+const UNLIKES: TableDefinition<&[u8], ()> = TableDefinition::new("unlikes");
 
 #[derive(Debug)]
 struct Subject {
@@ -28,46 +41,236 @@ impl FromStr for Subject {
     }
 }
 
+/// A subject's whole liker list, streaming its pages in order if it's paged
+fn get_likers(
+    likes: &ReadOnlyTable<&[u8], &[u8]>,
+    format: Format,
+    mut codec: Option<&mut ValueCodec>,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    let mut value = |v: &[u8]| match codec.as_mut() {
+        Some(codec) => codec.decompress(v),
+        None => Ok(v.to_vec()),
+    };
+    let Some(found) = likes.get(key)? else {
+        return Ok(None)
+    };
+    if format.paging.is_none() {
+        return Ok(Some(value(found.value())?))
+    }
+    let header = Header::from_bytes(found.value())?;
+    let (first, last) = paging::page_range(key, &header);
+    let mut pages = vec![];
+    for page in likes.range(&*first..=&*last)? {
+        pages.push(value(page?.1.value())?);
+    }
+    Ok(Some(format.encoding.join(None, pages.iter().map(|p| &p[..]))))
+}
+
+/// Every like on the records of a did, streamed from a range over the prefix
+fn likes_for_authority<'a>(
+    likes: &'a ReadOnlyTable<&[u8], &[u8]>,
+    format: Format,
+    mut codec: Option<&'a mut ValueCodec>,
+    did: &str,
+    collection: Option<&'a str>,
+) -> Result<impl Iterator<Item = Result<authority::Like>> + 'a> {
+    let prefix = authority::prefix(format, did, collection)?;
+    let end = listing::after_prefix(&prefix);
+    let rows = likes.range(&*prefix..&*end)?.map(|kv| {
+        let (k, v) = kv?;
+        Ok((k.value().to_vec(), v.value().to_vec()))
+    });
+    Ok(authority::likes(format, prefix, collection, rows, move |v| match codec.as_mut() {
+        Some(codec) => codec.decompress(&v),
+        None => Ok(v),
+    }))
+}
+
+/// One page of a subject's likers from the listing table
+fn list_likers(
+    listing: &ReadOnlyTable<&[u8], ()>,
+    key: &[u8],
+    order: Order,
+    limit: usize,
+    cursor: Option<&Cursor>,
+) -> Result<Page> {
+    let prefix = listing::prefix(key);
+    let start = listing::start_key(&prefix, order, cursor);
+    let key_of = |kv: Result<(AccessGuard<&[u8]>, AccessGuard<()>), StorageError>| -> Result<Vec<u8>> {
+        Ok(kv?.0.value().to_vec())
+    };
+    match order {
+        Order::OldestFirst => listing::page_from_keys(&prefix, cursor, limit, listing.range(&*start..)?.map(key_of)),
+        Order::NewestFirst => listing::page_from_keys(&prefix, cursor, limit, listing.range(..&*start)?.rev().map(key_of)),
+    }
+}
+
+/// One page of a did's likes from the forward table
+fn list_liked(
+    forward: &ReadOnlyTable<&[u8], &str>,
+    encoding: Encoding,
+    did: &str,
+    order: Order,
+    limit: usize,
+    cursor: Option<&Cursor>,
+) -> Result<forward::Page> {
+    let prefix = encoding.liker_prefix(did);
+    let start = listing::start_key(&prefix, order, cursor);
+    let kv_of = |kv: Result<(AccessGuard<&[u8]>, AccessGuard<&str>), StorageError>| -> Result<(Vec<u8>, Vec<u8>)> {
+        let (k, v) = kv?;
+        Ok((k.value().to_vec(), v.value().as_bytes().to_vec()))
+    };
+    match order {
+        Order::OldestFirst => forward::page_from_kvs(encoding, &prefix, cursor, limit, forward.range(&*start..)?.map(kv_of)),
+        Order::NewestFirst => forward::page_from_kvs(encoding, &prefix, cursor, limit, forward.range(..&*start)?.rev().map(kv_of)),
+    }
+}
+
+/// Whether a did has a like on a subject that it hasn't unliked
+fn has_liked(
+    liked: &ReadOnlyTable<(&[u8], &[u8]), ()>,
+    unlikes: &ReadOnlyTable<&[u8], ()>,
+    format: Format,
+    uri: &str,
+    did: &str,
+) -> Result<bool> {
+    let key = format.subject_key(uri);
+    let prefix = format.encoding.liker_prefix(did);
+    for kv in liked.range((&*key, &*prefix)..)? {
+        let (k, _) = kv?;
+        let (subject, liker) = k.value();
+        if subject != &key[..] || !liker.starts_with(&prefix) {
+            break
+        }
+        if unlikes.get(liker)?.is_none() {
+            return Ok(true)
+        }
+    }
+    Ok(false)
+}
+
 fn main() -> Result<()> {
+    let format = Format::from_args()?;
+    println!("{format}");
+    let mut codec = ValueCodec::from_args()?;
+    if let Some(codec) = &codec {
+        println!("values: {codec}");
+    }
+
+    let mode: Mode = cli::opt_or_default("mode")?;
+    println!("mode: {mode}");
+    let sampled_unlikes = match mode {
+        Mode::Forward | Mode::HasLiked => {
+            // from a throwaway open, so the timed one starts cold
+            let db = Database::open(DB_PATH)?;
+            let unlikes = db.begin_read()?.open_table(UNLIKES)?;
+            bench::sampled_unlikes(SUBJECTS_PATH, format, |liker| Ok(unlikes.get(liker)?.is_some()))?
+        }
+        _ => HashSet::new(),
+    };
+
+    phase::drop_page_cache();
     let db = Database::builder()
         .set_cache_size(64 * 2_usize.pow(20))
         .create(DB_PATH)?;
 
     let tx = db.begin_read()?;
+
+    // redb doesn't count cache hits
+    let phases = Phases::from_args(|| Ok(None))?;
+
+    if mode == Mode::List {
+        let listing = tx.open_table(LISTING)?;
+        return bench::run_list(SUBJECTS_PATH, format, &phases, |key, order, limit, cursor| {
+            list_likers(&listing, key, order, limit, cursor)
+        })
+    }
+    if mode == Mode::Forward {
+        let forward = tx.open_table(FORWARD)?;
+        return bench::run_forward(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |did, order, limit, cursor| list_liked(&forward, format.encoding, did, order, limit, cursor),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::HasLiked {
+        if !format.liked {
+            return Err(anyhow!("has-liked mode needs a db ingested with --liked (and --liked here too)"))
+        }
+        let liked = tx.open_table(LIKED)?;
+        let unlikes = tx.open_table(UNLIKES)?;
+        return bench::run_has_liked(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |uri, did| has_liked(&liked, &unlikes, format, uri, did),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::Batch {
+        return bench::run_batch(SUBJECTS_PATH, format, &phases, |uris| {
+            // a read transaction per batch, as a feed request would take
+            let likes = db.begin_read()?.open_table(LIKES)?;
+            uris.iter()
+                .map(|uri| get_likers(&likes, format, codec.as_mut(), &format.subject_key(uri)))
+                .collect()
+        })
+    }
+    if mode == Mode::Authority {
+        let likes = tx.open_table(LIKES)?;
+        return bench::run_authority(SUBJECTS_PATH, format, &phases, |did, collection| {
+            likes_for_authority(&likes, format, codec.as_mut(), did, collection)?.collect()
+        })
+    }
+    if mode == Mode::Count {
+        let counts = tx.open_table(COUNTS)?;
+        let unlikes = tx.open_table(UNLIKES)?;
+        return bench::run_count(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |key| Ok(counts.get(key)?.map(|c| c.value()).unwrap_or(0)),
+            |liker| Ok(unlikes.get(liker)?.is_some()),
+        )
+    }
+
     let likes = tx.open_table(LIKES)?;
 
-    println!("loop\tduration");
-    for n in 0..=2 {
+    let mut export = Export::from_args(Mode::Full)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
         let reader = io::BufReader::new(File::open(SUBJECTS_PATH)?);
+        let mut mismatches = Mismatches::default();
 
         let mut total = Duration::from_secs(0);
-        let mut times: HashMap<usize, Vec<f64>> = HashMap::new();
+        let mut times = Timings::default();
 
         for line in reader.lines() {
             let subject: Subject = line?.parse()?;
             let n_likes = subject.likers.split(';').count();
 
+            let key = format.subject_key(&subject.uri);
+
             let t0 = Instant::now();
-            let db_likers = likes.get(&*subject.uri)?.unwrap().value().to_string();
+            let db_likers = get_likers(&likes, format, codec.as_mut(), &key)?;
             let d = t0.elapsed();
 
             total += d;
-            (*times.entry(n_likes).or_insert(vec![])).push(d.as_nanos() as f64);
+            times.record(n_likes, d);
 
-            assert_eq!(db_likers, subject.likers);
-        }
-        println!("{n}\t{:.3}", total.as_secs_f32());
-
-        let mut res: Vec<_> = times
-            .iter()
-            .map(|(likes, group)|
-                (likes, group.into_iter().sum::<f64>() / (group.len() as f64) / 1000.0))
-            .collect();
-        res.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        for (likes, micros) in res {
-            println!("{likes}\t{micros:.3}");
+            mismatches.record(&subject.uri, verify::classify_likers(format.encoding, db_likers.as_deref(), &subject.likers)?);
         }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
     }
 
-    Ok(())
+    export.write()?;
+    verify::outcome(mismatched)
 }
@@ -0,0 +1,40 @@
+use std::time::Instant;
+use anyhow::Result;
+use kv_for_likes_common::{cli, dict};
+use kv_for_likes_common::paging::HEADER_LEN;
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
+
+const DB_PATH: &str = "./likes.redb";
+
+const LIKES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("likes");
+
+/// Sample liker values from an ingested db (built without `--dict`) and train
+/// a zstd dictionary for `--dict`
+fn main() -> Result<()> {
+    let want: usize = cli::opt("samples")?.unwrap_or(dict::DEFAULT_SAMPLES);
+    let dict_size: usize = cli::opt("dict-size")?.unwrap_or(dict::DEFAULT_DICT_SIZE);
+    let out: String = cli::opt("out")?.unwrap_or(dict::DEFAULT_DICT_PATH.to_string());
+
+    let db = Database::open(DB_PATH)?;
+    let tx = db.begin_read()?;
+    let likes = tx.open_table(LIKES)?;
+
+    let stride = dict::sample_stride(likes.len()?, want);
+    let mut samples = vec![];
+    for row in likes.iter()?.step_by(stride as usize) {
+        let value = row?.1.value().to_vec();
+        // page headers are exactly HEADER_LEN, and even one compact liker is longer
+        if value.len() == HEADER_LEN {
+            continue
+        }
+        samples.push(value);
+    }
+
+    let t0 = Instant::now();
+    let trained = dict::train(&samples, dict_size)?;
+    std::fs::write(&out, &trained)?;
+    println!("trained a {} byte dictionary from {} values in {:.1}s: {out}",
+        trained.len(), samples.len(), t0.elapsed().as_secs_f32());
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use anyhow::{anyhow, Result};
+use fs_extra::dir::get_size;
+use kv_for_likes_common::{bulk, cli, pipeline, Encoding, Format};
+use kv_for_likes_common::bulk::{Entry, Record, Sort};
+use rocksdb::{DB, DEFAULT_COLUMN_FAMILY_NAME, ColumnFamilyDescriptor, IngestExternalFileOptions, MergeOperands, Options, SstFileWriter};
+use rocksdb::merge_operator::MergeFn;
+
+pub mod profile;
+use profile::{Cf, Profile};
+
+const DB_PATH: &str = "./rocks.db";
+const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SORT_PATH: &str = "./rocks.sort";
+
+/// sst files are cut once they get this big
+const SST_FILE_BYTES: u64 = 256 * 2_u64.pow(20);
+
+fn join_merge(encoding: Encoding) -> impl MergeFn + Clone {
+    move |_new_key: &[u8], existing_val: Option<&[u8]>, operands: &MergeOperands| {
+        Some(encoding.join(existing_val, operands))
+    }
+}
+
+/// Sorted keys written out to sst files, cut every `SST_FILE_BYTES`
+struct SstFiles<'a> {
+    opts: &'a Options,
+    dir: PathBuf,
+    name: &'static str,
+    writer: Option<SstFileWriter<'a>>,
+    paths: Vec<PathBuf>,
+}
+
+impl<'a> SstFiles<'a> {
+    fn new(opts: &'a Options, dir: &Path, name: &'static str) -> Self {
+        SstFiles { opts, dir: dir.into(), name, writer: None, paths: vec![] }
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.writer.as_ref().is_some_and(|w| w.file_size() >= SST_FILE_BYTES) {
+            self.finish_file()?;
+        }
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                std::fs::create_dir_all(&self.dir)?;
+                let path = self.dir.join(format!("{}-{}.sst", self.name, self.paths.len()));
+                let writer = SstFileWriter::create(self.opts);
+                writer.open(&path)?;
+                self.paths.push(path);
+                self.writer.insert(writer)
+            }
+        };
+        writer.put(key, value)?;
+        Ok(())
+    }
+
+    fn finish_file(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Move the files into the db. they don't overlap each other, so they go
+    /// in together
+    fn ingest(mut self, db: &DB) -> Result<usize> {
+        self.finish_file()?;
+        if !self.paths.is_empty() {
+            let mut opts = IngestExternalFileOptions::default();
+            opts.set_move_files(true);
+            db.ingest_external_file_opts(&opts, self.paths.clone())?;
+        }
+        Ok(self.paths.len())
+    }
+}
+
+fn main() -> Result<()> {
+    let reader = io::BufReader::new(File::open(LIKES_PATH)?);
+
+    let format = Format::from_args()?;
+    println!("{format}");
+    bulk::check(format)?;
+    if Path::new(DB_PATH).exists() {
+        return Err(anyhow!("bulk load makes a new db, move {DB_PATH} out of the way first"))
+    }
+    let profile: Profile = cli::opt_or_default("profile")?;
+    let cache = profile.cache();
+    profile.show(&[Cf::Likes]);
+    let sort = Sort::from_args(SORT_PATH)?;
+
+    let likes_opts = {
+        let mut opts = profile.options(Cf::Likes, &cache);
+        opts.set_merge_operator_associative("join links", join_merge(format.encoding));
+        opts
+    };
+    // same default cf as ingest, so it can keep appending to this db
+    let db = DB::open_cf_descriptors(&{
+        let mut opts = likes_opts.clone();
+        opts.create_if_missing(true);
+        opts
+    }, DB_PATH, [ColumnFamilyDescriptor::new(DEFAULT_COLUMN_FAMILY_NAME, likes_opts.clone())])?;
+
+    let t0 = Instant::now();
+    let parsers = pipeline::parsers()?;
+    let mut likes = SstFiles::new(&likes_opts, &sort.dir, "likes");
+    let mut unlikes = SstFiles::new(&likes_opts, &sort.dir, "unlikes");
+    let loaded = pipeline::run(reader, parsers, |line| Entry::parse(format, line), |entries| {
+        bulk::load(entries, &sort, |key, likers| bulk::liker_values(format, key, likers, Ok), |record| match record {
+            Record::Likers { key, value } => likes.put(key, value),
+            Record::Unlike(key) => unlikes.put(key, b""),
+        })
+    })?;
+    let loaded_in = t0.elapsed();
+
+    // unlike keys can fall between subject keys, so they're ingested after,
+    // into a level above the likes
+    let files = likes.ingest(&db)? + unlikes.ingest(&db)?;
+    let _ = std::fs::remove_dir(&sort.dir);
+
+    let d = t0.elapsed();
+    println!("done in {:.1}s (sorted and written in {:.1}s). entries: {}, likes: {}, unlikes: {}, subjects: {}",
+        d.as_secs_f32(), loaded_in.as_secs_f32(), loaded.likes + loaded.unlikes, loaded.likes, loaded.unlikes, loaded.subjects);
+    println!("sort runs: {}, sst files: {}, size: {}", loaded.runs, files, get_size(DB_PATH)?);
+
+    Ok(())
+}
@@ -1,19 +1,34 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead};
-use std::str::FromStr;
+use std::io;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
 use fs_extra::dir::get_size;
-use rocksdb::{DB, Options, WriteOptions, MergeOperands};
+use kv_for_likes_common::{cli, counts, mixed, pipeline, Encoding, Format};
+use kv_for_likes_common::durability::Durability;
+use kv_for_likes_common::mixed::Reads;
+use kv_for_likes_common::paging::{self, Header};
+use rocksdb::{DB, DEFAULT_COLUMN_FAMILY_NAME, ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, WriteOptions, MergeOperands, WriteBatch};
+use rocksdb::merge_operator::MergeFn;
 use tinyjson::JsonValue;
 
+pub mod profile;
 pub mod store;
+use profile::{Cf, Profile};
 
 const DB_PATH: &str = "./rocks.db";
 const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
+
+const COUNTS_CF_NAME: &str = "counts";
+const LIKE_SUBJECTS_CF_NAME: &str = "like_subjects";
+const FORWARD_CF_NAME: &str = "forward";
+const LIKED_CF_NAME: &str = "liked";
 
 const CHECKIN_STEP: u64 = 10_000;
-const SYNC_STEP: u64 = 100;
+const DEFAULT_GROUP_ENTRIES: u64 = 100;
+const DEFAULT_GROUP_MS: u64 = 100;
 
 #[derive(Debug, Default)]
 struct Stats {
@@ -34,18 +49,20 @@ struct CreateEntry {
     did: String,
     rkey: String,
     uri: String,
+    /// its subject key
+    key: Vec<u8>,
+    /// its `did!rkey`, as the format writes it
+    liker: Vec<u8>,
 }
 
 #[derive(Debug)]
 struct DeleteEntry {
-    did: String,
-    rkey: String,
+    liker: Vec<u8>,
 }
 
-impl FromStr for Action {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self> {
+impl Action {
+    /// Parse an entry and make its keys, which the parser threads do
+    fn parse(format: Format, s: &str) -> Result<Self> {
         let parsed: JsonValue = s.parse()?;
         let entry = <Vec<_>>::try_from(parsed)?;
         if entry.len() != 4 {
@@ -57,105 +74,299 @@ impl FromStr for Action {
         match action.as_str() {
             "c" => {
                 let uri = String::try_from(entry[3].clone())?;
-                Ok(Action::Create(CreateEntry { did, rkey, uri }))
+                let (key, liker) = (format.subject_key(&uri), format.liker(&did, &rkey));
+                Ok(Action::Create(CreateEntry { did, rkey, uri, key, liker }))
             }
             "d" => {
-                Ok(Action::Delete(DeleteEntry { did, rkey }))
+                Ok(Action::Delete(DeleteEntry { liker: format.liker(&did, &rkey) }))
             }
             _ => Err(anyhow!("need 'c' or 'd' for entry action type"))
         }
     }
 }
 
-fn show_update(d: Duration, path: &str, stats: &Stats) {
+fn show_update(d: Duration, path: &str, stats: &Stats, reads: &Reads) {
     let Ok(size) = get_size(path) else {
         return
     };
-    println!("{}\t{}\t{:.3}", stats.entries, size, d.as_secs_f32());
+    println!("{}\t{}\t{:.3}{}", stats.entries, size, d.as_secs_f32(), reads.checkin(stats.entries));
 }
 
-fn join_merge(
-    _new_key: &[u8],
-    existing_val: Option<&[u8]>,
-    operands: &MergeOperands,
-) -> Option<Vec<u8>> {
-    let mut res = vec![];
-    let mut first = true;
-    if let Some(ex) = existing_val {
-        for b in ex {
-            res.push(*b)
+/// Fetch a subject's whole liker list like `read` does, for `--readers`
+fn read_likers(db: &DB, format: Format, uri: &str) -> Result<()> {
+    let key = format.subject_key(uri);
+    let Some(found) = db.get(&key)? else {
+        return Ok(())
+    };
+    if format.paging.is_some() {
+        let header = Header::from_bytes(&found)?;
+        let first = paging::page_key(&key, 0);
+        for page in db.iterator(IteratorMode::From(&first, Direction::Forward)).take(header.pages as usize) {
+            page?;
         }
-        first = false;
     }
-    for op in operands {
-        if first {
-            first = false;
-        } else {
-            res.push(b';')
+    Ok(())
+}
+
+/// How entries get committed, from `--group-commit`
+#[derive(Debug, Clone, Copy)]
+enum Commit {
+    /// each entry is its own write
+    PerEntry,
+    /// entries gather into one batch until there are `--group-entries` of them
+    /// (default `DEFAULT_GROUP_ENTRIES`) or `--group-ms` (default
+    /// `DEFAULT_GROUP_MS`) have passed since the first, then go in one write,
+    /// so `--durability sync` syncs once per batch, and a periodic sync waits
+    /// for the end of the batch it falls in
+    Group { entries: u64, interval: Duration },
+}
+
+impl Commit {
+    fn from_args() -> Result<Self> {
+        if !cli::flag("group-commit") {
+            return Ok(Commit::PerEntry)
         }
-        for b in op {
-            res.push(*b)
+        let entries = cli::opt("group-entries")?.unwrap_or(DEFAULT_GROUP_ENTRIES);
+        if entries == 0 {
+            return Err(anyhow!("group entries must be at least 1"))
         }
+        let interval = Duration::from_millis(cli::opt("group-ms")?.unwrap_or(DEFAULT_GROUP_MS));
+        Ok(Commit::Group { entries, interval })
     }
-    Some(res)
+}
+
+impl fmt::Display for Commit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Commit::PerEntry => write!(f, "a write per entry"),
+            Commit::Group { entries, interval } =>
+                write!(f, "a write per group of up to {entries} entries or {}ms", interval.as_millis()),
+        }
+    }
+}
+
+/// Writes waiting to be committed together, with the page headers and like
+/// subjects they've changed so far, so later entries read their own writes
+struct Group {
+    batch: WriteBatch,
+    entries: u64,
+    started: Instant,
+    headers: HashMap<Vec<u8>, Header>,
+    /// `None` for one deleted in this group
+    like_subjects: HashMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl Group {
+    fn new() -> Self {
+        Group {
+            batch: WriteBatch::default(),
+            entries: 0,
+            started: Instant::now(),
+            headers: HashMap::new(),
+            like_subjects: HashMap::new(),
+        }
+    }
+
+    fn header(&self, db: &DB, key: &[u8]) -> Result<Option<Header>> {
+        if let Some(header) = self.headers.get(key) {
+            return Ok(Some(*header))
+        }
+        db.get(key)?.map(|h| Header::from_bytes(&h)).transpose()
+    }
+
+    fn put_header(&mut self, key: &[u8], header: Header) {
+        self.batch.put(key, header.to_bytes());
+        self.headers.insert(key.to_vec(), header);
+    }
+
+    fn like_subject(&self, db: &DB, cf: &ColumnFamily, liker: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.like_subjects.get(liker) {
+            Some(subject) => Ok(subject.clone()),
+            None => Ok(db.get_cf(cf, liker)?),
+        }
+    }
+
+    fn put_like_subject(&mut self, cf: &ColumnFamily, liker: &[u8], subject: &[u8]) {
+        self.batch.put_cf(cf, liker, subject);
+        self.like_subjects.insert(liker.to_vec(), Some(subject.to_vec()));
+    }
+
+    fn delete_like_subject(&mut self, cf: &ColumnFamily, liker: &[u8]) {
+        self.batch.delete_cf(cf, liker);
+        self.like_subjects.insert(liker.to_vec(), None);
+    }
+
+    /// Count one more entry, and say whether it's time to commit
+    fn add_entry(&mut self, commit: Commit) -> bool {
+        if self.entries == 0 {
+            self.started = Instant::now();
+        }
+        self.entries += 1;
+        match commit {
+            Commit::PerEntry => true,
+            Commit::Group { entries, interval } => self.entries >= entries || self.started.elapsed() >= interval,
+        }
+    }
+
+    fn commit(&mut self, db: &DB, opts: &WriteOptions) -> Result<()> {
+        if self.entries > 0 {
+            db.write_opt(std::mem::take(&mut self.batch), opts)?;
+        }
+        self.entries = 0;
+        self.headers.clear();
+        self.like_subjects.clear();
+        Ok(())
+    }
+}
+
+fn join_merge(encoding: Encoding) -> impl MergeFn + Clone {
+    move |_new_key: &[u8], existing_val: Option<&[u8]>, operands: &MergeOperands| {
+        Some(encoding.join(existing_val, operands))
+    }
+}
+
+fn add_merge(_key: &[u8], existing_val: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    counts::sum(existing_val, operands).ok().map(|count| counts::to_bytes(count).to_vec())
 }
 
 fn main() -> Result<()> {
     let reader = io::BufReader::new(File::open(LIKES_PATH)?);
 
-    let db = DB::open(&{
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        opts.set_merge_operator_associative("join links", join_merge);
+    let format = Format::from_args()?;
+    println!("{format}");
+    let profile: Profile = cli::opt_or_default("profile")?;
+    let cache = profile.cache();
+    // rocks opens any cf that isn't listed with default options, including
+    // the default cf, which would lose its merge operator
+    let mut cfs = vec![ColumnFamilyDescriptor::new(DEFAULT_COLUMN_FAMILY_NAME, {
+        let mut opts = profile.options(Cf::Likes, &cache);
+        opts.set_merge_operator_associative("join links", join_merge(format.encoding));
         opts
-    }, DB_PATH)?;
+    })];
+    let mut shown = vec![Cf::Likes];
+    if format.counts {
+        cfs.push(ColumnFamilyDescriptor::new(COUNTS_CF_NAME, {
+            let mut opts = profile.options(Cf::Counts, &cache);
+            opts.set_merge_operator_associative("add counts", add_merge);
+            opts
+        }));
+        cfs.push(ColumnFamilyDescriptor::new(LIKE_SUBJECTS_CF_NAME, profile.options(Cf::LikeSubjects, &cache)));
+        shown.extend([Cf::Counts, Cf::LikeSubjects]);
+    }
+    if format.forward_index {
+        cfs.push(ColumnFamilyDescriptor::new(FORWARD_CF_NAME, profile.options(Cf::Forward, &cache)));
+        shown.push(Cf::Forward);
+    }
+    if format.liked {
+        cfs.push(ColumnFamilyDescriptor::new(LIKED_CF_NAME, profile.options(Cf::Liked, &cache)));
+        shown.push(Cf::Liked);
+    }
+    profile.show(&shown);
 
-    let sync_opts = {
-        let mut opts = WriteOptions::default();
-        opts.set_sync(true);
+    let db = DB::open_cf_descriptors(&{
+        let mut opts = profile.options(Cf::Likes, &cache);
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        opts.set_merge_operator_associative("join links", join_merge(format.encoding));
         opts
-    };
+    }, DB_PATH, cfs)?;
+    let forward_cf = format.forward_index.then(|| db.cf_handle(FORWARD_CF_NAME).expect("opened with the forward cf"));
+    let liked_cf = format.liked.then(|| db.cf_handle(LIKED_CF_NAME).expect("opened with the liked cf"));
+    let counted = format.counts.then(|| (
+        db.cf_handle(COUNTS_CF_NAME).expect("opened with the counts cf"),
+        db.cf_handle(LIKE_SUBJECTS_CF_NAME).expect("opened with the like_subjects cf"),
+    ));
 
-    let nosync_opts = {
+    let commit = Commit::from_args()?;
+    println!("commits: {commit}");
+    let durability = Durability::from_args()?;
+    println!("durability: {durability}");
+
+    // periodic syncs are a synced WAL flush on top of buffered writes
+    let write_opts = {
         let mut opts = WriteOptions::default();
-        opts.set_sync(false);
-        opts.disable_wal(true);
+        opts.set_sync(durability == Durability::Sync);
+        opts.disable_wal(durability == Durability::None);
         opts
     };
 
     let mut stats: Stats = Default::default();
     let t0 = Instant::now();
 
-    {
-        let v = db.get(b"at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l")?;
-        println!("{}", String::from_utf8(v.unwrap())?);
-    }
-
-    if false { for line in reader.lines() {
-        let action: Action = line?.parse()?;
-        let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
-        let sync = (stats.entries % SYNC_STEP) == (SYNC_STEP - 1);
-
-        let opts = if sync { &sync_opts } else { &nosync_opts };
-        match action {
-            Action::Create(entry) => {
-                let key = &entry.uri.as_bytes();
-                let val = format!("{}!{}", entry.did, entry.rkey);
-                db.merge_opt(key, &val.as_bytes(), opts)?;
-                stats.likes += 1;
-            },
-            Action::Delete(entry) => {
-                let key = format!("{}!{}", entry.did, entry.rkey);
-                db.put_opt(&key.as_bytes(), b"", opts)?;
-                stats.unlikes += 1;
-            },
-        }
-        stats.entries += 1;
+    let readers = mixed::readers()?;
+    let parsers = pipeline::parsers()?;
+    mixed::run(readers, SUBJECTS_PATH, || Ok(|uri: &str| read_likers(&db, format, uri)), |reads| {
+        pipeline::run(reader, parsers, |line| Action::parse(format, line), |actions| {
+            let mut group = Group::new();
+            let mut syncs = durability.syncs();
+            let mut sync = false;
+            for action in actions {
+                let action = action?;
+                let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
+                sync |= syncs.due();
 
-        if checkin {
-            show_update(t0.elapsed(), DB_PATH, &stats);
-        }
-    } }
+                match action {
+                    Action::Create(entry) => {
+                        let (key, val) = (entry.key, entry.liker);
+                        if let Some(paging) = format.paging {
+                            let header = group.header(&db, &key)?;
+                            if header.is_none() {
+                                stats.subjects += 1;
+                            }
+                            let (page, header) = paging.append(header);
+                            group.put_header(&key, header);
+                            group.batch.merge(paging::page_key(&key, page), &val);
+                        } else {
+                            group.batch.merge(&key, &val);
+                        }
+                        if format.listing {
+                            group.batch.put(format.listing_key(&entry.uri, &entry.did, &entry.rkey), b"");
+                        }
+                        if let Some(forward_cf) = forward_cf {
+                            group.batch.put_cf(forward_cf, &val, &entry.uri);
+                        }
+                        if let Some(liked_cf) = liked_cf {
+                            group.batch.put_cf(liked_cf, format.like_key(&entry.uri, &entry.did, &entry.rkey), b"");
+                        }
+                        if let Some((counts_cf, like_subjects_cf)) = counted {
+                            group.batch.merge_cf(counts_cf, &key, counts::to_bytes(1));
+                            group.put_like_subject(like_subjects_cf, &val, &key);
+                        }
+                        stats.likes += 1;
+                    },
+                    Action::Delete(entry) => {
+                        let key = entry.liker;
+                        group.batch.put(&key, b"");
+                        if let Some(forward_cf) = forward_cf {
+                            group.batch.delete_cf(forward_cf, &key);
+                        }
+                        if let Some((counts_cf, like_subjects_cf)) = counted {
+                            // likes from before the data started have no subject to take from
+                            if let Some(subject) = group.like_subject(&db, like_subjects_cf, &key)? {
+                                group.batch.merge_cf(counts_cf, &subject, counts::to_bytes(-1));
+                                group.delete_like_subject(like_subjects_cf, &key);
+                            }
+                        }
+                        stats.unlikes += 1;
+                    },
+                }
+                if group.add_entry(commit) {
+                    group.commit(&db, &write_opts)?;
+                    if sync {
+                        db.flush_wal(true)?;
+                        sync = false;
+                    }
+                }
+                stats.entries += 1;
+
+                if checkin {
+                    reads.check()?;
+                    show_update(t0.elapsed(), DB_PATH, &stats, reads);
+                }
+            }
+            group.commit(&db, &write_opts)
+        })
+    })?;
 
     db.flush()?;
 
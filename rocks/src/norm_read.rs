@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use kv_for_likes_common::cli;
+use kv_for_likes_common::bench::{Export, Mode};
+use kv_for_likes_common::histogram::Timings;
+use kv_for_likes_common::phase::{self, Phases};
+use kv_for_likes_common::verify::{self, Mismatches};
+
+pub mod profile;
+pub mod store;
+use profile::{Cf, Profile};
+use store::{Store, DEFAULT_ID_CACHE};
+
+const DB_PATH: &str = "./normed.rocks";
+const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
+
+#[derive(Debug)]
+struct Subject {
+    uri: String,
+    likers: String,
+}
+
+impl FromStr for Subject {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((uri, likers)) = s.split_once('|') {
+            Ok(Subject { uri: uri.into(), likers: likers.into() })
+        } else {
+            Err(anyhow!("failed to split input"))
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let profile: Profile = cli::opt_or_default("profile")?;
+    profile.show(&[Cf::Ids, Cf::Names, Cf::Links, Cf::Liked]);
+    let id_cache = NonZeroUsize::new(DEFAULT_ID_CACHE).expect("default is nonzero");
+    phase::drop_page_cache();
+    let store = Store::open(DB_PATH, id_cache, profile)?;
+    let phases = Phases::from_args(|| Ok(Some(store.cache_counters())))?;
+
+    let mut export = Export::from_args(Mode::Full)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
+        let reader = io::BufReader::new(File::open(SUBJECTS_PATH)?);
+        let mut mismatches = Mismatches::default();
+
+        let mut total = Duration::from_secs(0);
+        let mut times = Timings::default();
+
+        for line in reader.lines() {
+            let subject: Subject = line?.parse()?;
+            let likes = subject.likers.split(';').count();
+
+            let t0 = Instant::now();
+            let res = store.likers(&subject.uri)?;
+            let d = t0.elapsed();
+
+            total += d;
+            times.record(likes, d);
+
+            let expected: Vec<_> = subject.likers.split(';').map(|l| l.to_string()).collect();
+            mismatches.record(&subject.uri, verify::classify(&res, &expected));
+        }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+
+        export.push(phase, vec![("reads", times)]);
+    }
+
+    export.write()?;
+    verify::outcome(mismatched)
+}
@@ -0,0 +1,243 @@
+//! named tuning profiles for the rocks column families, picked with `--profile`
+//!
+//! each profile sets, per column family, the compression, an optional zstd
+//! level for the bottommost level, an optional zstd dictionary size, bloom
+//! filter bits, block size and a fixed-length prefix extractor, plus the size
+//! of one block cache that every column family shares, so a profile uses the
+//! same memory however many column families a format opens.
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use kv_for_likes_common::phase::CacheCounters;
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options, SliceTransform};
+use rocksdb::statistics::Ticker;
+
+/// store ids are 8 bytes, and every `links` key starts with one
+const ID_PREFIX_LEN: usize = 8;
+/// zstd's default window, for the compression options
+const ZSTD_WINDOW_BITS: i32 = -14;
+/// zstd's own default level, for when only the dictionary is being set
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+/// rocks trains each file's dictionary from up to this many times its size of samples
+const DICT_TRAIN_RATIO: i32 = 100;
+/// same as the app-level dictionaries from `train-dict` in redb and rusqlite
+const DICT_BYTES: i32 = kv_for_likes_common::dict::DEFAULT_DICT_SIZE as i32;
+
+/// The column families we tune, by what they hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cf {
+    /// the default cf of the plain (non-normalized) db
+    Likes,
+    Ids,
+    Names,
+    Links,
+    /// maintained like counts, with `--counts`
+    Counts,
+    /// each like's subject, for taking deletes off the count
+    LikeSubjects,
+    /// every like by its liker, with `--forward-index`
+    Forward,
+    /// a key per like under its subject and liker, with `--liked`
+    Liked,
+}
+
+impl fmt::Display for Cf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cf::Likes => write!(f, "likes"),
+            Cf::Ids => write!(f, "ids"),
+            Cf::Names => write!(f, "names"),
+            Cf::Links => write!(f, "links"),
+            Cf::Counts => write!(f, "counts"),
+            Cf::LikeSubjects => write!(f, "like_subjects"),
+            Cf::Forward => write!(f, "forward"),
+            Cf::Liked => write!(f, "liked"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tuning {
+    pub compression: DBCompressionType,
+    /// zstd level for the bottommost level, if it's compressed differently
+    pub bottommost_zstd: Option<i32>,
+    /// max bytes for the zstd dictionary rocks trains per sst, from its own samples
+    pub dict_bytes: Option<i32>,
+    pub bloom_bits: Option<f64>,
+    pub block_size: usize,
+    pub prefix_len: Option<usize>,
+}
+
+impl Tuning {
+    pub fn apply(&self, opts: &mut Options, cache: &Cache) {
+        opts.set_compression_type(self.compression);
+        let dict_bytes = self.dict_bytes.unwrap_or(0);
+        if dict_bytes > 0 {
+            opts.set_compression_options(ZSTD_WINDOW_BITS, ZSTD_DEFAULT_LEVEL, 0, dict_bytes);
+            opts.set_zstd_max_train_bytes(dict_bytes * DICT_TRAIN_RATIO);
+        }
+        if let Some(level) = self.bottommost_zstd {
+            opts.set_bottommost_compression_type(DBCompressionType::Zstd);
+            opts.set_bottommost_compression_options(ZSTD_WINDOW_BITS, level, 0, dict_bytes, true);
+            opts.set_bottommost_zstd_max_train_bytes(dict_bytes * DICT_TRAIN_RATIO, true);
+        }
+        if let Some(len) = self.prefix_len {
+            opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(len));
+        }
+        let mut bb_opts = BlockBasedOptions::default();
+        bb_opts.set_block_size(self.block_size);
+        if let Some(bits) = self.bloom_bits {
+            bb_opts.set_bloom_filter(bits, false);
+        }
+        bb_opts.set_block_cache(cache);
+        opts.set_block_based_table_factory(&bb_opts);
+    }
+}
+
+/// Block cache hits and misses across every cf, from the statistics of db
+/// options that had `enable_statistics`
+pub fn cache_counters(opts: &Options) -> CacheCounters {
+    CacheCounters {
+        hits: opts.get_ticker_count(Ticker::BlockCacheHit),
+        misses: opts.get_ticker_count(Ticker::BlockCacheMiss),
+    }
+}
+
+fn compression_name(compression: DBCompressionType) -> &'static str {
+    match compression {
+        DBCompressionType::None => "none",
+        DBCompressionType::Snappy => "snappy",
+        DBCompressionType::Zlib => "zlib",
+        DBCompressionType::Bz2 => "bz2",
+        DBCompressionType::Lz4 => "lz4",
+        DBCompressionType::Lz4hc => "lz4hc",
+        DBCompressionType::Zstd => "zstd",
+    }
+}
+
+impl fmt::Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", compression_name(self.compression))?;
+        if let Some(level) = self.bottommost_zstd {
+            write!(f, ", bottommost zstd {level}")?;
+        }
+        if let Some(bytes) = self.dict_bytes {
+            write!(f, ", {}KiB dictionary", bytes / 1024)?;
+        }
+        match self.bloom_bits {
+            Some(bits) => write!(f, ", bloom {bits} bits/key")?,
+            None => write!(f, ", no bloom")?,
+        }
+        write!(f, ", {}KiB blocks", self.block_size / 1024)?;
+        if let Some(len) = self.prefix_len {
+            write!(f, ", {len}-byte prefix")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// rocks' own defaults, plus the 64MiB cache `read` always had
+    #[default]
+    Default,
+    /// blooms and a big cache for gets, prefix seeks on `links`
+    PointLookup,
+    /// zstd everywhere and big blocks, trading reads for space
+    Compact,
+    /// zstd with per-file trained dictionaries on the liker values
+    ZstdDict,
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "default" => Ok(Profile::Default),
+            "point-lookup" => Ok(Profile::PointLookup),
+            "compact" => Ok(Profile::Compact),
+            "zstd-dict" => Ok(Profile::ZstdDict),
+            _ => Err(anyhow!("unknown profile {s:?}, expected default, point-lookup, compact or zstd-dict")),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Profile::Default => write!(f, "default"),
+            Profile::PointLookup => write!(f, "point-lookup"),
+            Profile::Compact => write!(f, "compact"),
+            Profile::ZstdDict => write!(f, "zstd-dict"),
+        }
+    }
+}
+
+impl Profile {
+    pub fn tuning(&self, cf: Cf) -> Tuning {
+        match self {
+            Profile::Default => Tuning {
+                compression: DBCompressionType::Snappy,
+                bottommost_zstd: None,
+                dict_bytes: None,
+                bloom_bits: None,
+                block_size: 4 * 1024,
+                prefix_len: None,
+            },
+            Profile::PointLookup => Tuning {
+                compression: DBCompressionType::Lz4,
+                bottommost_zstd: Some(3),
+                dict_bytes: None,
+                bloom_bits: Some(10.0),
+                block_size: 4 * 1024,
+                prefix_len: (cf == Cf::Links).then_some(ID_PREFIX_LEN),
+            },
+            Profile::Compact => Tuning {
+                compression: DBCompressionType::Zstd,
+                bottommost_zstd: Some(19),
+                dict_bytes: None,
+                bloom_bits: Some(10.0),
+                block_size: 64 * 1024,
+                prefix_len: None,
+            },
+            Profile::ZstdDict => Tuning {
+                compression: DBCompressionType::Zstd,
+                bottommost_zstd: Some(ZSTD_DEFAULT_LEVEL),
+                // ids and names are mostly unique strings, dictionaries are for liker lists
+                dict_bytes: matches!(cf, Cf::Likes | Cf::Links).then_some(DICT_BYTES),
+                bloom_bits: Some(10.0),
+                block_size: 4 * 1024,
+                prefix_len: None,
+            },
+        }
+    }
+
+    /// The size of the block cache shared by every cf
+    pub fn cache_mb(&self) -> usize {
+        match self {
+            Profile::PointLookup => 512,
+            Profile::Default | Profile::Compact | Profile::ZstdDict => 64,
+        }
+    }
+
+    /// A new block cache for one db, to pass to each of its cfs' options
+    pub fn cache(&self) -> Cache {
+        Cache::new_lru_cache(self.cache_mb() * 2_usize.pow(20))
+    }
+
+    /// Fresh options for a column family, tuned for this profile, using the
+    /// db's shared `cache`
+    pub fn options(&self, cf: Cf, cache: &Cache) -> Options {
+        let mut opts = Options::default();
+        self.tuning(cf).apply(&mut opts, cache);
+        opts
+    }
+
+    /// Record the profile and its tuning for each cf in the run output
+    pub fn show(&self, cfs: &[Cf]) {
+        println!("profile: {self}, {}MiB block cache shared by every cf", self.cache_mb());
+        for cf in cfs {
+            println!("  {cf}: {}", self.tuning(*cf));
+        }
+    }
+}
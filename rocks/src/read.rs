@@ -1,14 +1,30 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use rocksdb::{DB, Options, MergeOperands, BlockBasedOptions, Cache};
+use kv_for_likes_common::{authority, bench, cli, counts, forward, Encoding, Format, Layout};
+use kv_for_likes_common::bench::{Export, Mode};
+use kv_for_likes_common::histogram::Timings;
+use kv_for_likes_common::listing::{self, Cursor, Order, Page};
+use kv_for_likes_common::paging::{self, Header};
+use kv_for_likes_common::phase::{self, Phases};
+use kv_for_likes_common::verify::{self, Mismatches};
+use rocksdb::{DB, DEFAULT_COLUMN_FAMILY_NAME, ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, MergeOperands, ReadOptions};
+use rocksdb::merge_operator::MergeFn;
+
+pub mod profile;
+use profile::{Cf, Profile};
 
 const DB_PATH: &str = "./rocks.db";
 const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
 
+const COUNTS_CF_NAME: &str = "counts";
+const LIKE_SUBJECTS_CF_NAME: &str = "like_subjects";
+const FORWARD_CF_NAME: &str = "forward";
+const LIKED_CF_NAME: &str = "liked";
+
 #[derive(Debug)]
 struct Subject {
     uri: String,
@@ -26,78 +42,288 @@ impl FromStr for Subject {
     }
 }
 
-fn join_merge(
-    _new_key: &[u8],
-    existing_val: Option<&[u8]>,
-    operands: &MergeOperands,
-) -> Option<Vec<u8>> {
-    let mut res = vec![];
-    let mut first = true;
-    if let Some(ex) = existing_val {
-        for b in ex {
-            res.push(*b)
-        }
-        first = false;
+fn join_merge(encoding: Encoding) -> impl MergeFn + Clone {
+    move |_new_key: &[u8], existing_val: Option<&[u8]>, operands: &MergeOperands| {
+        Some(encoding.join(existing_val, operands))
     }
-    for op in operands {
-        if first {
-            first = false;
-        } else {
-            res.push(b';')
+}
+
+fn add_merge(_key: &[u8], existing_val: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    counts::sum(existing_val, operands).ok().map(|count| counts::to_bytes(count).to_vec())
+}
+
+/// A subject's whole liker list, streaming its pages in order if it's paged
+fn get_likers(db: &DB, format: Format, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(found) = db.get(key)? else {
+        return Ok(None)
+    };
+    if format.paging.is_none() {
+        return Ok(Some(found))
+    }
+    let header = Header::from_bytes(&found)?;
+    let first = paging::page_key(key, 0);
+    let mut pages = vec![];
+    for page in db.iterator(IteratorMode::From(&first, Direction::Forward)).take(header.pages as usize) {
+        pages.push(page?.1);
+    }
+    Ok(Some(format.encoding.join(None, pages.iter().map(|p| &p[..]))))
+}
+
+/// Each subject's whole liker list, from one `multi_get` for the subjects
+/// and, if they're paged, one more for all of their pages
+fn get_batch(db: &DB, format: Format, uris: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+    let keys: Vec<_> = uris.iter().map(|uri| format.subject_key(uri)).collect();
+    let found = db.multi_get(&keys).into_iter().collect::<Result<Vec<_>, _>>()?;
+    if format.paging.is_none() {
+        return Ok(found)
+    }
+    let mut page_keys = vec![];
+    for (key, header) in keys.iter().zip(found) {
+        let Some(header) = header else {
+            page_keys.push(None);
+            continue
+        };
+        let header = Header::from_bytes(&header)?;
+        page_keys.push(Some((0..header.pages).map(|page| paging::page_key(key, page)).collect::<Vec<_>>()));
+    }
+    let mut pages = db.multi_get(page_keys.iter().flatten().flatten()).into_iter();
+    page_keys
+        .into_iter()
+        .map(|keys| keys.map(|keys| {
+            let likers = pages
+                .by_ref()
+                .take(keys.len())
+                .map(|page| page?.ok_or_else(|| anyhow!("missing a page")))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(format.encoding.join(None, likers.iter().map(|p| &p[..])))
+        }).transpose())
+        .collect()
+}
+
+/// Whether a did has a like on a subject that it hasn't unliked, from its
+/// keys in the liked cf. unlikes are liker keys in the default cf
+fn has_liked(db: &DB, liked_cf: &ColumnFamily, format: Format, uri: &str, did: &str) -> Result<bool> {
+    let prefix = format.liked_prefix(uri, did);
+    let liker_at = format.like_prefix(uri).len();
+    for kv in db.iterator_cf(liked_cf, IteratorMode::From(&prefix, Direction::Forward)) {
+        let (key, _) = kv?;
+        if !key.starts_with(&prefix) {
+            break
         }
-        for b in op {
-            res.push(*b)
+        if db.get(&key[liker_at..])?.is_none() {
+            return Ok(true)
         }
     }
-    Some(res)
+    Ok(false)
+}
+
+/// How to scan from a [`listing::start_key`]: forward from it, or backward
+/// from just before it, since a reverse seek would return the key itself
+fn listing_scan(start: &[u8], order: Order) -> (IteratorMode<'_>, ReadOptions) {
+    let mut opts = ReadOptions::default();
+    let mode = match order {
+        Order::OldestFirst => IteratorMode::From(start, Direction::Forward),
+        Order::NewestFirst => {
+            opts.set_iterate_upper_bound(start);
+            IteratorMode::End
+        }
+    };
+    (mode, opts)
+}
+
+/// One page of a did's likes from the forward cf
+fn list_liked(
+    db: &DB,
+    forward_cf: &ColumnFamily,
+    encoding: Encoding,
+    did: &str,
+    order: Order,
+    limit: usize,
+    cursor: Option<&Cursor>,
+) -> Result<forward::Page> {
+    let prefix = encoding.liker_prefix(did);
+    let start = listing::start_key(&prefix, order, cursor);
+    let (mode, opts) = listing_scan(&start, order);
+    let kvs = db
+        .iterator_cf_opt(forward_cf, opts, mode)
+        .map(|kv| {
+            let (k, v) = kv?;
+            Ok((k.into_vec(), v.into_vec()))
+        });
+    forward::page_from_kvs(encoding, &prefix, cursor, limit, kvs)
+}
+
+/// Every like on the records of a did, streamed forward from its prefix
+fn likes_for_authority<'a>(
+    db: &'a DB,
+    format: Format,
+    did: &str,
+    collection: Option<&'a str>,
+) -> Result<impl Iterator<Item = Result<authority::Like>> + 'a> {
+    let prefix = authority::prefix(format, did, collection)?;
+    let rows = db
+        .iterator(IteratorMode::From(&prefix, Direction::Forward))
+        .map(|kv| {
+            let (k, v) = kv?;
+            Ok((k.into_vec(), v.into_vec()))
+        });
+    Ok(authority::likes(format, prefix, collection, rows, Ok))
+}
+
+/// One page of a subject's likers from its listing keys
+fn list_likers(db: &DB, key: &[u8], order: Order, limit: usize, cursor: Option<&Cursor>) -> Result<Page> {
+    let prefix = listing::prefix(key);
+    let start = listing::start_key(&prefix, order, cursor);
+    let (mode, opts) = listing_scan(&start, order);
+    let keys = db
+        .iterator_opt(mode, opts)
+        .map(|kv| Ok(kv?.0.into_vec()));
+    listing::page_from_keys(&prefix, cursor, limit, keys)
 }
 
 fn main() -> Result<()> {
-    let db = DB::open(&{
-        let mut opts = Options::default();
+    let format = Format::from_args()?;
+    println!("{format}");
+    let profile: Profile = cli::opt_or_default("profile")?;
+    let cache = profile.cache();
+    // rocks opens any cf that isn't listed with default options, including
+    // the default cf, which would lose its merge operator
+    let mut cfs = vec![ColumnFamilyDescriptor::new(DEFAULT_COLUMN_FAMILY_NAME, {
+        let mut opts = profile.options(Cf::Likes, &cache);
+        opts.set_merge_operator_associative("join links", join_merge(format.encoding));
+        opts
+    })];
+    let mut shown = vec![Cf::Likes];
+    if format.counts {
+        cfs.push(ColumnFamilyDescriptor::new(COUNTS_CF_NAME, {
+            let mut opts = profile.options(Cf::Counts, &cache);
+            opts.set_merge_operator_associative("add counts", add_merge);
+            opts
+        }));
+        cfs.push(ColumnFamilyDescriptor::new(LIKE_SUBJECTS_CF_NAME, profile.options(Cf::LikeSubjects, &cache)));
+        shown.extend([Cf::Counts, Cf::LikeSubjects]);
+    }
+    if format.forward_index {
+        cfs.push(ColumnFamilyDescriptor::new(FORWARD_CF_NAME, profile.options(Cf::Forward, &cache)));
+        shown.push(Cf::Forward);
+    }
+    if format.liked {
+        cfs.push(ColumnFamilyDescriptor::new(LIKED_CF_NAME, profile.options(Cf::Liked, &cache)));
+        shown.push(Cf::Liked);
+    }
+    profile.show(&shown);
+
+    let direct_reads = cli::flag("direct-reads");
+    if direct_reads {
+        println!("direct reads: on");
+    }
+    let opts = {
+        let mut opts = profile.options(Cf::Likes, &cache);
         opts.create_if_missing(true);
         // opts.optimize_for_point_lookup(64 * 2_u64.pow(20));
-        let cache = Cache::new_lru_cache(64 * 2_usize.pow(20));
-        let mut bb_opts = BlockBasedOptions::default();
-        bb_opts.set_block_cache(&cache);
-        opts.set_block_based_table_factory(&bb_opts);
-        opts.set_merge_operator_associative("join links", join_merge);
+        opts.set_merge_operator_associative("join links", join_merge(format.encoding));
+        opts.set_use_direct_reads(direct_reads);
+        opts.enable_statistics();
         opts
-    }, DB_PATH)?;
+    };
+
+    let mode: Mode = cli::opt_or_default("mode")?;
+    println!("mode: {mode}");
+    let sampled_unlikes = match mode {
+        Mode::Forward | Mode::HasLiked => {
+            // from a throwaway open with its own block cache, so the timed
+            // one starts cold. unlikes are plain keys in the default cf
+            let db = DB::open_for_read_only(&profile.options(Cf::Likes, &profile.cache()), DB_PATH, false)?;
+            bench::sampled_unlikes(SUBJECTS_PATH, format, |liker| Ok(db.get(liker)?.is_some()))?
+        }
+        _ => HashSet::new(),
+    };
+
+    phase::drop_page_cache();
+    let db = DB::open_cf_descriptors(&opts, DB_PATH, cfs)?;
+    let phases = Phases::from_args(|| Ok(Some(profile::cache_counters(&opts))))?;
 
-    println!("loop\tduration");
-    for n in 0..=2 {
+    if mode == Mode::List {
+        return bench::run_list(SUBJECTS_PATH, format, &phases, |key, order, limit, cursor| {
+            list_likers(&db, key, order, limit, cursor)
+        })
+    }
+    if mode == Mode::Forward {
+        let forward_cf = db.cf_handle(FORWARD_CF_NAME).ok_or_else(|| anyhow!("forward mode needs --forward-index"))?;
+        return bench::run_forward(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |did, order, limit, cursor| list_liked(&db, forward_cf, format.encoding, did, order, limit, cursor),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::HasLiked {
+        let liked_cf = db.cf_handle(LIKED_CF_NAME)
+            .ok_or_else(|| anyhow!("has-liked mode needs a db ingested with --liked (and --liked here too)"))?;
+        return bench::run_has_liked(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |uri, did| has_liked(&db, liked_cf, format, uri, did),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::Batch {
+        return bench::run_batch(SUBJECTS_PATH, format, &phases, |uris| get_batch(&db, format, uris))
+    }
+    if mode == Mode::Authority {
+        if format.layout == Layout::Forward && format.encoding == Encoding::Compact {
+            // unlikes are liker entries in the default cf, starting with the same did bytes
+            return Err(anyhow!("authority mode can't tell unlikes from compact forward subject keys, try --layout did-prefixed"))
+        }
+        return bench::run_authority(SUBJECTS_PATH, format, &phases, |did, collection| {
+            likes_for_authority(&db, format, did, collection)?.collect()
+        })
+    }
+    if mode == Mode::Count {
+        let counts_cf = db.cf_handle(COUNTS_CF_NAME).expect("opened with the counts cf");
+        return bench::run_count(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |key| Ok(db.get_cf(counts_cf, key)?.map(|c| counts::from_bytes(&c)).transpose()?.unwrap_or(0)),
+            |liker| Ok(db.get(liker)?.is_some()),
+        )
+    }
+
+    let mut export = Export::from_args(Mode::Full)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
         let reader = io::BufReader::new(File::open(SUBJECTS_PATH)?);
+        let mut mismatches = Mismatches::default();
 
         let mut total = Duration::from_secs(0);
-        let mut times: HashMap<usize, Vec<f64>> = HashMap::new();
+        let mut times = Timings::default();
 
         for line in reader.lines() {
             let subject: Subject = line?.parse()?;
             let likes = subject.likers.split(';').count();
 
+            let key = format.subject_key(&subject.uri);
+
             let t0 = Instant::now();
-            let res = db.get(subject.uri.into_bytes())?.unwrap();
+            let res = get_likers(&db, format, &key)?;
             let d = t0.elapsed();
 
             total += d;
-            (*times.entry(likes).or_insert(vec![])).push(d.as_nanos() as f64);
+            times.record(likes, d);
 
-            assert_eq!(String::from_utf8(res)?, subject.likers);
+            mismatches.record(&subject.uri, verify::classify_likers(format.encoding, res.as_deref(), &subject.likers)?);
         }
-        println!("{n}\t{:.3}", total.as_secs_f32());
-
-        let mut res: Vec<_> = times
-            .iter()
-            .map(|(likes, group)|
-                (likes, group.into_iter().sum::<f64>() / (group.len() as f64) / 1000.0))
-            .collect();
-        res.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        for (likes, micros) in res {
-            println!("{likes}\t{micros:.3}");
-        }
-
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
     }
 
-    Ok(())
+    export.write()?;
+    verify::outcome(mismatched)
 }
@@ -1,53 +1,216 @@
-use rocksdb::{DB, Options, MergeOperands, ColumnFamilyDescriptor, WriteBatch};
+//! normalized like storage: every did, collection and subject uri is interned
+//! to an 8-byte id, and subjects map to a list of liker ids + rkeys
+//!
+//! column families:
+//! - `ids`: interned bytes -> id, plus the id sequence
+//! - `names`: id -> interned bytes, so ids can be resolved again
+//! - `links`: subject uri id -> concatenated liker entries (did id + encoded
+//!   rkey), and `<did id>:<encoded rkey>` -> subject uri id for deletes
+//! - `liked`: `<subject uri id><did id><encoded rkey>` for each like, so
+//!   has-liked is a seek to the subject and did's prefix
+use std::fmt;
+use std::num::NonZeroUsize;
+use rocksdb::{DB, ColumnFamily, Direction, IteratorMode, Options, MergeOperands, ColumnFamilyDescriptor, WriteBatch, WriteOptions};
 use anyhow::{anyhow, Result};
+use kv_for_likes_common::aturi::AtUri;
+use kv_for_likes_common::tid;
+use lru::LruCache;
+use kv_for_likes_common::phase::CacheCounters;
+use crate::profile::{self, Cf, Profile};
 
 
 const IDS_CF_NAME: &str = "ids";
-const IDS_SEQ_KEY: &[u8] = b"id seq";
+const NAMES_CF_NAME: &str = "names";
+const LINKS_CF_NAME: &str = "links";
+const LIKED_CF_NAME: &str = "liked";
+const IDS_SEQ_KEY: &[u8] = b"id.seq";
+/// How many ids to reserve each time the sequence is persisted
+pub const ID_BLOCK: u64 = 1024;
+pub const DEFAULT_ID_CACHE: usize = 100_000;
+/// Marks a `names` value as a subject uri (did id, collection id, encoded
+/// rkey) rather than a plain string, which never starts with a nul
+const URI_NAME_TAG: u8 = 0x00;
 
 
 pub struct Store {
     db: DB,
     ids: StoreIdSeq,
+    id_cache: IdCache,
+    write_opts: WriteOptions,
+    /// the db options, which hold its statistics
+    opts: Options,
 }
 
-struct StoreIdSeq {
-    current_id: u64,
+#[derive(Debug)]
+pub struct CreateEntry {
+    pub did: String,
+    pub rkey: String,
+    pub uri: String,
+}
+
+#[derive(Debug)]
+pub struct DeleteEntry {
+    pub did: String,
+    pub rkey: String,
 }
 
+/// A like whose subject isn't an `at://did/collection/rkey` uri
 #[derive(Debug)]
-struct StoreID(u64);
+pub struct BadSubject {
+    pub uri: String,
+    pub reason: String,
+}
+
+impl fmt::Display for BadSubject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad like subject {:?}: {}", self.uri, self.reason)
+    }
+}
+
+impl std::error::Error for BadSubject {}
+
+/// Hands out ids from blocks reserved in the db
+///
+/// the persisted sequence is the end of the last reserved block, and is synced
+/// before any id from the block is handed out. after a crash we start again
+/// from there, skipping whatever was left of the block, so ids are never reused.
+pub struct StoreIdSeq {
+    current_id: u64,
+    reserved_until: u64,
+    block: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreID(pub u64);
+
+impl StoreID {
+    pub const LEN: usize = 8;
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Ok(bytes): std::result::Result<[u8; 8], _> = bytes.try_into() else {
+            return Err(anyhow!("ids must be 8 bytes, found {}", bytes.len()))
+        };
+        Ok(StoreID(u64::from_le_bytes(bytes)))
+    }
+}
 
-fn join_id_merge(
+/// Bounded LRU of interned bytes -> id, in front of the ids column family
+///
+/// ids are never reassigned, so a cached id stays valid for good. new ids are
+/// only inserted once their batch is written, so a failed write can't leave
+/// the cache pointing at an id the db never got.
+pub struct IdCache {
+    lru: LruCache<Vec<u8>, Vec<u8>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl IdCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        IdCache { lru: LruCache::new(capacity), hits: 0, misses: 0 }
+    }
+
+    /// Get a cached id, or try `load` on a miss and remember what it finds
+    pub fn get_or_load(
+        &mut self,
+        key: &[u8],
+        load: impl FnOnce() -> Result<Option<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(id) = self.lru.get(key) {
+            self.hits += 1;
+            return Ok(Some(id.clone()))
+        }
+        self.misses += 1;
+        let found = load()?;
+        if let Some(id) = &found {
+            self.lru.put(key.to_vec(), id.clone());
+        }
+        Ok(found)
+    }
+
+    pub fn insert(&mut self, key: &[u8], id: &[u8]) {
+        self.lru.put(key.to_vec(), id.to_vec());
+    }
+
+    /// Fraction of lookups served from the cache so far
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Link entries are a fixed-size did id followed by a self-delimiting encoded
+/// rkey, so they can just be concatenated
+fn concat_merge(
     _new_key: &[u8],
     existing_val: Option<&[u8]>,
     operands: &MergeOperands,
 ) -> Option<Vec<u8>> {
-    let mut res = vec![];
-    let mut first = true;
-    if let Some(ex) = existing_val {
-        for b in ex {
-            res.push(*b)
-        }
-        first = false;
-    }
+    let mut res = existing_val.map(|ex| ex.to_vec()).unwrap_or_default();
     for op in operands {
-        if first {
-            first = false;
-        } else {
-            res.push(b';')
-        }
-        for b in op {
-            res.push(*b)
-        }
+        res.extend_from_slice(op);
     }
     Some(res)
 }
 
+/// Split a subject's links value into its liker entries
+fn link_entries(value: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut entries = vec![];
+    let mut rest = value;
+    while !rest.is_empty() {
+        let Some(after_id) = rest.get(StoreID::LEN..) else {
+            return Err(anyhow!("truncated liker id in links value"))
+        };
+        let (_, n) = tid::decode(after_id)?;
+        let (entry, after) = rest.split_at(StoreID::LEN + n);
+        entries.push(entry);
+        rest = after;
+    }
+    Ok(entries)
+}
+
+fn liker_entry(did_id: StoreID, rkey: &str) -> Vec<u8> {
+    let mut entry = did_id.to_bytes().to_vec();
+    tid::encode_into(rkey, &mut entry);
+    entry
+}
+
+fn liked_key(uri_id: &[u8], did_id: StoreID, rkey: &str) -> Vec<u8> {
+    [uri_id, &liker_entry(did_id, rkey)].concat()
+}
+
+fn link_key(did_id: StoreID, rkey: &str) -> Vec<u8> {
+    let mut key = did_id.to_bytes().to_vec();
+    key.push(b':');
+    tid::encode_into(rkey, &mut key);
+    key
+}
+
+/// The did, collection and rkey of a like's subject
+fn parse_subject(uri: &str) -> Result<(String, String, String), BadSubject> {
+    let bad = |reason: String| BadSubject { uri: uri.to_string(), reason };
+    match uri.parse::<AtUri>() {
+        Ok(AtUri::DidCollectionKey(did, collection, rkey)) if did.starts_with("did:") => {
+            Ok((did, collection, rkey))
+        }
+        Ok(_) => Err(bad("expected at://did/collection/rkey".to_string())),
+        Err(e) => Err(bad(e.to_string())),
+    }
+}
+
 impl StoreIdSeq {
-    pub fn new(db: &mut DB) -> Result<Self> {
+    pub fn new(db: &DB, block: u64) -> Result<Self> {
+        if block == 0 {
+            return Err(anyhow!("id blocks must hold at least one id"))
+        }
         let ids_cf = db.cf_handle(IDS_CF_NAME).expect("db must have ids column family");
-        let current_id = match db.get_cf(&ids_cf, IDS_SEQ_KEY)? {
+        let current_id = match db.get_cf(ids_cf, IDS_SEQ_KEY)? {
             Some(existing) => {
                 let Ok(bytes): std::result::Result<[u8; 8], _> = existing.try_into() else {
                     return Err(anyhow!("failed to get 8 bytes for u64 conversion for id sequence"))
@@ -55,61 +218,286 @@ impl StoreIdSeq {
                 u64::from_le_bytes(bytes)
             }
             None => {
-                println!("no initial db seq found: starting at 0");
-                0
+                println!("no initial db seq found: starting at 1");
+                1
             }
         };
-        Ok(StoreIdSeq { current_id })
+        Ok(StoreIdSeq { current_id, reserved_until: current_id, block })
     }
 
-    pub fn next(&mut self, db: &mut DB, batch: &mut WriteBatch) -> StoreID {
-        let ids_cf = db.cf_handle(IDS_CF_NAME).expect("db must have ids column family");
+    pub fn next(&mut self, db: &DB) -> Result<StoreID> {
+        if self.current_id == self.reserved_until {
+            let ids_cf = db.cf_handle(IDS_CF_NAME).expect("db must have ids column family");
+            let reserved_until = self.current_id + self.block;
+            let mut opts = WriteOptions::default();
+            opts.set_sync(true);
+            db.put_cf_opt(ids_cf, IDS_SEQ_KEY, reserved_until.to_le_bytes(), &opts)?;
+            self.reserved_until = reserved_until;
+        }
         let yours = StoreID(self.current_id);
         self.current_id += 1;
-        batch.put_cf(&ids_cf, IDS_SEQ_KEY, self.current_id.to_le_bytes());
-        yours
+        Ok(yours)
     }
 }
 
 impl Store {
     pub fn new(path: &str) -> Result<Self> {
-        let ids_cf_d = ColumnFamilyDescriptor::new("ids", Options::default());
-        let links_cf_d = ColumnFamilyDescriptor::new("links", {
-            let mut opts = Options::default();
-            opts.set_merge_operator_associative("join links", join_id_merge);
+        Self::open(path, NonZeroUsize::new(DEFAULT_ID_CACHE).expect("default is nonzero"), Profile::default())
+    }
+
+    pub fn open(path: &str, id_cache: NonZeroUsize, profile: Profile) -> Result<Self> {
+        let cache = profile.cache();
+        let ids_cf_d = ColumnFamilyDescriptor::new(IDS_CF_NAME, profile.options(Cf::Ids, &cache));
+        let names_cf_d = ColumnFamilyDescriptor::new(NAMES_CF_NAME, profile.options(Cf::Names, &cache));
+        let links_cf_d = ColumnFamilyDescriptor::new(LINKS_CF_NAME, {
+            let mut opts = profile.options(Cf::Links, &cache);
+            opts.set_merge_operator_associative("concat links", concat_merge);
             opts
         });
-        let mut db = DB::open_cf_descriptors(
-            &{
-                let mut opts = Options::default();
-                opts.create_if_missing(true);
-                opts.create_missing_column_families(true);
-                opts
-            },
-            path,
-            vec![ids_cf_d, links_cf_d],
-        )?;
-        let ids = StoreIdSeq::new(&mut db)?;
+        let liked_cf_d = ColumnFamilyDescriptor::new(LIKED_CF_NAME, profile.options(Cf::Liked, &cache));
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        opts.enable_statistics();
+        let db = DB::open_cf_descriptors(&opts, path, vec![ids_cf_d, names_cf_d, links_cf_d, liked_cf_d])?;
+        let ids = StoreIdSeq::new(&db, ID_BLOCK)?;
+
+        Ok(Store { db, ids, id_cache: IdCache::new(id_cache), write_opts: WriteOptions::default(), opts })
+    }
+
+    /// Block cache hits and misses since the store opened
+    pub fn cache_counters(&self) -> CacheCounters {
+        profile::cache_counters(&self.opts)
+    }
 
-        Ok(Store { db, ids })
+    fn cf(&self, name: &str) -> &ColumnFamily {
+        self.db.cf_handle(name).expect("store opens all of its column families")
     }
 
+    /// Sync each following write, or skip the WAL entirely
+    pub fn set_sync(&mut self, sync: bool) {
+        let mut opts = WriteOptions::default();
+        opts.set_sync(sync);
+        opts.disable_wal(!sync);
+        self.write_opts = opts;
+    }
+
+    pub fn id_cache(&self) -> &IdCache {
+        &self.id_cache
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.db.flush()?)
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Result<u64> {
+        Ok(self.ids.next(&self.db)?.0)
+    }
+
+    /// Look up an existing id straight from the db, without assigning one
+    fn find(&self, actual: &[u8]) -> Result<Option<StoreID>> {
+        self.db.get_cf(self.cf(IDS_CF_NAME), actual)?
+            .map(|id| StoreID::from_bytes(&id))
+            .transpose()
+    }
+
+    /// Look up an existing id through the cache, without assigning one
+    fn lookup(&mut self, actual: &[u8]) -> Result<Option<StoreID>> {
+        let ids_cf = self.db.cf_handle(IDS_CF_NAME).expect("store opens all of its column families");
+        self.id_cache.get_or_load(actual, || Ok(self.db.get_cf(ids_cf, actual)?))?
+            .map(|id| StoreID::from_bytes(&id))
+            .transpose()
+    }
+
+    /// Get the id for some bytes, assigning a new one in the batch if they've
+    /// never been seen. new ids get their `names` entry in the same batch, and
+    /// go in `new_ids` (checked first, so the batch doesn't assign one twice)
+    /// to be cached by [`Store::write`] once the batch is in.
+    fn intern_in(
+        &mut self,
+        batch: &mut WriteBatch,
+        new_ids: &mut Vec<(Vec<u8>, StoreID)>,
+        actual: &[u8],
+        name: &[u8],
+    ) -> Result<StoreID> {
+        if let Some((_, id)) = new_ids.iter().find(|(new, _)| new == actual) {
+            return Ok(*id)
+        }
+        if let Some(id) = self.lookup(actual)? {
+            return Ok(id)
+        }
+        let id = self.ids.next(&self.db)?;
+        batch.put_cf(self.cf(IDS_CF_NAME), actual, id.to_bytes());
+        batch.put_cf(self.cf(NAMES_CF_NAME), id.to_bytes(), name);
+        new_ids.push((actual.to_vec(), id));
+        Ok(id)
+    }
+
+    /// Write a batch, and then cache the ids it assigned
+    fn write(&mut self, batch: WriteBatch, new_ids: Vec<(Vec<u8>, StoreID)>) -> Result<()> {
+        self.db.write_opt(batch, &self.write_opts)?;
+        for (actual, id) in new_ids {
+            self.id_cache.insert(&actual, &id.to_bytes());
+        }
+        Ok(())
+    }
+
+    pub fn intern(&mut self, actual: &str) -> Result<StoreID> {
+        let (mut batch, mut new_ids) = (WriteBatch::default(), vec![]);
+        let id = self.intern_in(&mut batch, &mut new_ids, actual.as_bytes(), actual.as_bytes())?;
+        self.write(batch, new_ids)?;
+        Ok(id)
+    }
+
+    /// The string for an id: subject uri ids resolve to their full at-uri
+    pub fn resolve(&self, id: StoreID) -> Result<String> {
+        let Some(name) = self.db.get_cf(self.cf(NAMES_CF_NAME), id.to_bytes())? else {
+            return Err(anyhow!("no name for id {}", id.0))
+        };
+        let Some(smol_uri) = name.strip_prefix(&[URI_NAME_TAG]) else {
+            return Ok(String::from_utf8(name)?)
+        };
+        let Some((did_id, rest)) = smol_uri.split_at_checked(StoreID::LEN) else {
+            return Err(anyhow!("truncated did id in uri {}", id.0))
+        };
+        let Some((collection_id, rkey)) = rest.split_at_checked(StoreID::LEN) else {
+            return Err(anyhow!("truncated collection id in uri {}", id.0))
+        };
+        let did = self.resolve(StoreID::from_bytes(did_id)?)?;
+        let collection = self.resolve(StoreID::from_bytes(collection_id)?)?;
+        let (rkey, _) = tid::decode(rkey)?;
+        Ok(format!("at://{did}/{collection}/{rkey}"))
+    }
+
+    /// Record a like. Subjects that aren't record uris fail with a [`BadSubject`].
+    pub fn add_like(&mut self, entry: CreateEntry) -> Result<()> {
+        let (target_did, collection, rkey) = parse_subject(&entry.uri)?;
+        let (mut batch, mut new_ids) = (WriteBatch::default(), vec![]);
+
+        let liker_did_id = self.intern_in(&mut batch, &mut new_ids, entry.did.as_bytes(), entry.did.as_bytes())?;
+        let target_did_id = self.intern_in(&mut batch, &mut new_ids, target_did.as_bytes(), target_did.as_bytes())?;
+        let collection_id = self.intern_in(&mut batch, &mut new_ids, collection.as_bytes(), collection.as_bytes())?;
+
+        let smol_uri = [&target_did_id.to_bytes()[..], &collection_id.to_bytes(), &tid::encode(&rkey)].concat();
+        let uri_name = [&[URI_NAME_TAG][..], &smol_uri].concat();
+        let uri_id = self.intern_in(&mut batch, &mut new_ids, &smol_uri, &uri_name)?;
+
+        let links_cf = self.cf(LINKS_CF_NAME);
+        batch.put_cf(links_cf, link_key(liker_did_id, &entry.rkey), uri_id.to_bytes());
+        batch.merge_cf(links_cf, uri_id.to_bytes(), liker_entry(liker_did_id, &entry.rkey));
+        batch.put_cf(self.cf(LIKED_CF_NAME), liked_key(&uri_id.to_bytes(), liker_did_id, &entry.rkey), b"");
+
+        self.write(batch, new_ids)
+    }
+
+    /// Remove a like, if we have it. Returns whether anything was removed.
+    pub fn remove_like(&mut self, entry: DeleteEntry) -> Result<bool> {
+        let Some(did_id) = self.lookup(entry.did.as_bytes())? else {
+            return Ok(false)
+        };
+        let links_cf = self.cf(LINKS_CF_NAME);
+        let link_key = link_key(did_id, &entry.rkey);
+        let Some(uri_id) = self.db.get_cf(links_cf, &link_key)? else {
+            // delete link to uri we never had -- if we're backfilled this is a weirder thing to happen
+            return Ok(false)
+        };
+
+        let links = self.db.get_cf(links_cf, &uri_id)?.unwrap_or_default();
+        let removing = liker_entry(did_id, &entry.rkey);
+        let remaining = link_entries(&links)?
+            .into_iter()
+            .filter(|e| *e != removing)
+            .collect::<Vec<_>>()
+            .concat();
+
         let mut batch = WriteBatch::default();
-        let id = self.ids.next(&mut self.db, &mut batch);
-        self.db.write(batch)?;
-        Ok(id.0)
+        batch.delete_cf(links_cf, &link_key);
+        batch.delete_cf(self.cf(LIKED_CF_NAME), liked_key(&uri_id, did_id, &entry.rkey));
+        if remaining.is_empty() {
+            batch.delete_cf(links_cf, &uri_id);
+        } else {
+            batch.put_cf(links_cf, &uri_id, remaining);
+        }
+        self.db.write_opt(batch, &self.write_opts)?;
+        Ok(true)
     }
-}
 
+    /// The subject's uri id, if it's ever been liked
+    fn uri_id(&self, uri: &str) -> Result<Option<StoreID>> {
+        let (did, collection, rkey) = parse_subject(uri)?;
+        let Some(did_id) = self.find(did.as_bytes())? else {
+            return Ok(None)
+        };
+        let Some(collection_id) = self.find(collection.as_bytes())? else {
+            return Ok(None)
+        };
+        let smol_uri = [&did_id.to_bytes()[..], &collection_id.to_bytes(), &tid::encode(&rkey)].concat();
+        self.find(&smol_uri)
+    }
+
+    /// The subject's links value, if it has any likes
+    fn links(&self, uri: &str) -> Result<Option<Vec<u8>>> {
+        let Some(uri_id) = self.uri_id(uri)? else {
+            return Ok(None)
+        };
+        Ok(self.db.get_cf(self.cf(LINKS_CF_NAME), uri_id.to_bytes())?)
+    }
+
+    /// Every `did!rkey` that liked the uri, in the order they were added
+    pub fn likers(&self, uri: &str) -> Result<Vec<String>> {
+        let Some(links) = self.links(uri)? else {
+            return Ok(vec![])
+        };
+        link_entries(&links)?
+            .into_iter()
+            .map(|entry| {
+                let (did_id, rkey) = entry.split_at(StoreID::LEN);
+                let did = self.resolve(StoreID::from_bytes(did_id)?)?;
+                Ok(format!("{did}!{}", tid::decode(rkey)?.0))
+            })
+            .collect()
+    }
+
+    /// Whether the did has a like on the uri, by seeking to their `liked` keys
+    pub fn has_liked(&self, uri: &str, did: &str) -> Result<bool> {
+        let Some(did_id) = self.find(did.as_bytes())? else {
+            return Ok(false)
+        };
+        let Some(uri_id) = self.uri_id(uri)? else {
+            return Ok(false)
+        };
+        let prefix = [uri_id.to_bytes(), did_id.to_bytes()].concat();
+        match self.db.iterator_cf(self.cf(LIKED_CF_NAME), IteratorMode::From(&prefix, Direction::Forward)).next() {
+            Some(kv) => Ok(kv?.0.starts_with(&prefix)),
+            None => Ok(false),
+        }
+    }
 
-fn add(a: u32, b: u32) -> u32 {
-    a + b
+    pub fn count(&self, uri: &str) -> Result<usize> {
+        match self.links(uri)? {
+            Some(links) => Ok(link_entries(&links)?.len()),
+            None => Ok(0),
+        }
+    }
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+
+    fn add(a: u32, b: u32) -> u32 {
+        a + b
+    }
+
+    fn open_ids_db(path: &std::path::Path) -> DB {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        DB::open_cf(&opts, path, [IDS_CF_NAME]).unwrap()
+    }
 
     #[test]
     fn test_add() {
@@ -118,7 +506,162 @@ mod tests {
 
     #[test]
     fn test_store() {
-        let mut store = Store::new("test.rocks").unwrap();
+        let dir = TempDir::new().unwrap();
+        let mut store = Store::new(dir.path().to_str().unwrap()).unwrap();
         assert_eq!(store.next().unwrap(), 1);
+        assert_eq!(store.next().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_id_seq_persists_once_per_block() {
+        let dir = TempDir::new().unwrap();
+        let db = open_ids_db(dir.path());
+        let ids_cf = db.cf_handle(IDS_CF_NAME).unwrap();
+        let mut seq = StoreIdSeq::new(&db, 4).unwrap();
+        let persisted = || u64::from_le_bytes(db.get_cf(ids_cf, IDS_SEQ_KEY).unwrap().unwrap().try_into().unwrap());
+
+        assert_eq!(seq.next(&db).unwrap(), StoreID(1));
+        assert_eq!(persisted(), 5);
+        for expected in 2..=4 {
+            assert_eq!(seq.next(&db).unwrap(), StoreID(expected));
+            assert_eq!(persisted(), 5);
+        }
+        assert_eq!(seq.next(&db).unwrap(), StoreID(5));
+        assert_eq!(persisted(), 9);
+    }
+
+    #[test]
+    fn test_id_seq_never_reuses_after_crash() {
+        let dir = TempDir::new().unwrap();
+        let handed_out: Vec<_> = {
+            let db = open_ids_db(dir.path());
+            let mut seq = StoreIdSeq::new(&db, 4).unwrap();
+            // drop mid-block without any shutdown bookkeeping
+            (0..6).map(|_| seq.next(&db).unwrap()).collect()
+        };
+        let db = open_ids_db(dir.path());
+        let mut seq = StoreIdSeq::new(&db, 4).unwrap();
+        let next = seq.next(&db).unwrap();
+        assert!(handed_out.iter().all(|id| id.0 < next.0));
+        assert_eq!(next, StoreID(9));
+    }
+
+    const LIKER: &str = "did:plc:hdhoaan3xa3jiuq4fg4mefid";
+    const OTHER_LIKER: &str = "did:web:example.com";
+    const SUBJECT: &str = "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/3lccjpbhjck2l";
+
+    fn open_store(dir: &TempDir) -> Store {
+        Store::new(dir.path().to_str().unwrap()).unwrap()
+    }
+
+    fn like(did: &str, rkey: &str, uri: &str) -> CreateEntry {
+        CreateEntry { did: did.to_string(), rkey: rkey.to_string(), uri: uri.to_string() }
+    }
+
+    fn unlike(did: &str, rkey: &str) -> DeleteEntry {
+        DeleteEntry { did: did.to_string(), rkey: rkey.to_string() }
+    }
+
+    #[test]
+    fn test_intern_resolve() {
+        let dir = TempDir::new().unwrap();
+        let id = {
+            let mut store = open_store(&dir);
+            let id = store.intern(LIKER).unwrap();
+            assert_eq!(store.intern(LIKER).unwrap(), id);
+            assert_ne!(store.intern(OTHER_LIKER).unwrap(), id);
+            assert_eq!(store.resolve(id).unwrap(), LIKER);
+            id
+        };
+        let mut store = open_store(&dir);
+        assert_eq!(store.intern(LIKER).unwrap(), id);
+        assert_eq!(store.resolve(id).unwrap(), LIKER);
+        assert!(store.resolve(StoreID(999_999)).is_err());
+    }
+
+    #[test]
+    fn test_add_like() {
+        let dir = TempDir::new().unwrap();
+        let mut store = open_store(&dir);
+        assert_eq!(store.likers(SUBJECT).unwrap(), Vec::<String>::new());
+        store.add_like(like(LIKER, "3ld53lnvvhc2w", SUBJECT)).unwrap();
+        store.add_like(like(OTHER_LIKER, "self", SUBJECT)).unwrap();
+        assert_eq!(store.likers(SUBJECT).unwrap(), [
+            format!("{LIKER}!3ld53lnvvhc2w"),
+            format!("{OTHER_LIKER}!self"),
+        ]);
+        assert_eq!(store.count(SUBJECT).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_self_like_assigns_one_id() {
+        let dir = TempDir::new().unwrap();
+        let mut store = open_store(&dir);
+        let own_post = format!("at://{LIKER}/app.bsky.feed.post/3lccjpbhjck2l");
+        store.add_like(like(LIKER, "self", &own_post)).unwrap();
+        let liker_id = store.intern(LIKER).unwrap();
+        assert_eq!(store.resolve(liker_id).unwrap(), LIKER);
+        assert_eq!(store.next().unwrap(), liker_id.0 + 3);
+        assert_eq!(store.likers(&own_post).unwrap(), [format!("{LIKER}!self")]);
+    }
+
+    #[test]
+    fn test_subject_resolves_to_uri() {
+        let dir = TempDir::new().unwrap();
+        let mut store = open_store(&dir);
+        // the subject's did liking its own post shares its id
+        store.add_like(like("did:plc:iyr4nadkkq2toocambsr3inz", "3ld53lnvvhc2w", SUBJECT)).unwrap();
+        let (_, collection, rkey) = parse_subject(SUBJECT).unwrap();
+        let did_id = store.find(b"did:plc:iyr4nadkkq2toocambsr3inz").unwrap().unwrap();
+        let collection_id = store.find(collection.as_bytes()).unwrap().unwrap();
+        let smol_uri = [&did_id.to_bytes()[..], &collection_id.to_bytes(), &tid::encode(&rkey)].concat();
+        let uri_id = store.find(&smol_uri).unwrap().unwrap();
+        assert_eq!(store.resolve(uri_id).unwrap(), SUBJECT);
+    }
+
+    #[test]
+    fn test_remove_like() {
+        let dir = TempDir::new().unwrap();
+        let mut store = open_store(&dir);
+        store.add_like(like(LIKER, "3ld53lnvvhc2w", SUBJECT)).unwrap();
+        store.add_like(like(OTHER_LIKER, "self", SUBJECT)).unwrap();
+
+        assert!(store.remove_like(unlike(LIKER, "3ld53lnvvhc2w")).unwrap());
+        assert_eq!(store.likers(SUBJECT).unwrap(), [format!("{OTHER_LIKER}!self")]);
+        // already gone, never liked, and unknown did
+        assert!(!store.remove_like(unlike(LIKER, "3ld53lnvvhc2w")).unwrap());
+        assert!(!store.remove_like(unlike(OTHER_LIKER, "3ld53lnvvhc2w")).unwrap());
+        assert!(!store.remove_like(unlike("did:plc:222222222222222222222222", "self")).unwrap());
+
+        assert!(store.remove_like(unlike(OTHER_LIKER, "self")).unwrap());
+        assert_eq!(store.count(SUBJECT).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_has_liked() {
+        let dir = TempDir::new().unwrap();
+        let mut store = open_store(&dir);
+        store.add_like(like(LIKER, "3ld53lnvvhc2w", SUBJECT)).unwrap();
+        store.add_like(like(LIKER, "3ld53lnvvhc2x", SUBJECT)).unwrap();
+        store.add_like(like(OTHER_LIKER, "self", "at://did:plc:iyr4nadkkq2toocambsr3inz/app.bsky.feed.post/other")).unwrap();
+        assert!(store.has_liked(SUBJECT, LIKER).unwrap());
+        assert!(!store.has_liked(SUBJECT, OTHER_LIKER).unwrap());
+        assert!(!store.has_liked(SUBJECT, "did:plc:222222222222222222222222").unwrap());
+        // still liked until both likes are gone
+        store.remove_like(unlike(LIKER, "3ld53lnvvhc2w")).unwrap();
+        assert!(store.has_liked(SUBJECT, LIKER).unwrap());
+        store.remove_like(unlike(LIKER, "3ld53lnvvhc2x")).unwrap();
+        assert!(!store.has_liked(SUBJECT, LIKER).unwrap());
+    }
+
+    #[test]
+    fn test_bad_subject() {
+        let dir = TempDir::new().unwrap();
+        let mut store = open_store(&dir);
+        for uri in ["at://alice.bsky.social/app.bsky.feed.post/3ld53lnvvhc2w", "at://did:web:example.com", "nope"] {
+            let err = store.add_like(like(LIKER, "3ld53lnvvhc2w", uri)).unwrap_err();
+            assert_eq!(err.downcast::<BadSubject>().unwrap().uri, uri);
+        }
+        assert!(store.find(LIKER.as_bytes()).unwrap().is_none());
     }
 }
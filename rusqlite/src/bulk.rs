@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use anyhow::{anyhow, Result};
+use kv_for_likes_common::{bulk, pipeline, Format};
+use kv_for_likes_common::bulk::{Entry, Record, Sort};
+use kv_for_likes_common::dict::ValueCodec;
+use rusqlite::Connection;
+
+const DB_PATH: &str = "./likes.db";
+const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SORT_PATH: &str = "./likes.db.sort";
+
+const MB_IN_KB: i64 = 2_i64.pow(10);
+const WRITE_CACHE: i64 = 100 * MB_IN_KB;
+
+const INSERT_LIKES_STATEMENT: &str =
+    "INSERT INTO likes (uri, likes) VALUES (?1, ?2)";
+
+const INSERT_UNLIKE_STATEMENT: &str =
+    "INSERT INTO unlikes (did_rkey) VALUES (?1)";
+
+fn main() -> Result<()> {
+    let reader = io::BufReader::new(File::open(LIKES_PATH)?);
+
+    let format = Format::from_args()?;
+    println!("{format}");
+    bulk::check(format)?;
+    let mut codec = ValueCodec::from_args()?;
+    if let Some(codec) = &codec {
+        println!("values: {codec}");
+    }
+    if Path::new(DB_PATH).exists() {
+        return Err(anyhow!("bulk load makes a new db, move {DB_PATH} out of the way first"))
+    }
+    let sort = Sort::from_args(SORT_PATH)?;
+
+    let mut conn = Connection::open(DB_PATH)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    // the load is one transaction, so there's nothing to sync until its commit
+    conn.pragma_update(None, "synchronous", "OFF")?;
+    conn.pragma_update(None, "cache_size", (-WRITE_CACHE).to_string())?;
+
+    let t0 = Instant::now();
+    let tx = conn.transaction()?;
+    // no keys yet: rows go in in key order, and the unique indexes that stand
+    // in for ingest's primary keys are built once they're all there
+    tx.execute(
+        "CREATE TABLE likes (
+            uri   blob NOT NULL,
+            likes blob NOT NULL
+        )",
+        (),
+    )?;
+    tx.execute(
+        "CREATE TABLE unlikes (
+            did_rkey blob NOT NULL
+        )",
+        (),
+    )?;
+
+    let parsers = pipeline::parsers()?;
+    let loaded = {
+        let mut likes_statement = tx.prepare(INSERT_LIKES_STATEMENT)?;
+        let mut unlike_statement = tx.prepare(INSERT_UNLIKE_STATEMENT)?;
+        pipeline::run(reader, parsers, |line| Entry::parse(format, line), |entries| {
+            bulk::load(entries, &sort, |key, likers| bulk::liker_values(format, key, likers, |v| match codec.as_mut() {
+                Some(codec) => codec.compress(&v),
+                None => Ok(v),
+            }), |record| {
+                match record {
+                    Record::Likers { key, value } => likes_statement.execute((key, value))?,
+                    Record::Unlike(key) => unlike_statement.execute((key,))?,
+                };
+                Ok(())
+            })
+        })?
+    };
+    let inserted = t0.elapsed();
+    tx.execute("CREATE UNIQUE INDEX likes_uri ON likes (uri)", ())?;
+    tx.execute("CREATE UNIQUE INDEX unlikes_did_rkey ON unlikes (did_rkey)", ())?;
+    tx.commit()?;
+    conn.pragma_update(None, "synchronous", "FULL")?;
+    // so the db file holds everything, for its size
+    conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")?;
+
+    let d = t0.elapsed();
+    println!("done in {:.1}s (indexed in {:.1}s). entries: {}, likes: {}, unlikes: {}, subjects: {}",
+        d.as_secs_f32(), (d - inserted).as_secs_f32(), loaded.likes + loaded.unlikes, loaded.likes, loaded.unlikes, loaded.subjects);
+    println!("sort runs: {}, size: {}", loaded.runs, Path::new(DB_PATH).metadata()?.len());
+
+    Ok(())
+}
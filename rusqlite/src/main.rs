@@ -1,17 +1,24 @@
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io;
 use std::path::Path;
-use std::str::FromStr;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use rusqlite::{Connection, TransactionBehavior};
+use kv_for_likes_common::{mixed, pipeline, Format};
+use kv_for_likes_common::dict::ValueCodec;
+use kv_for_likes_common::listing;
+use kv_for_likes_common::durability::Durability;
+use kv_for_likes_common::mixed::Reads;
+use kv_for_likes_common::paging::{self, Header};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
 use tinyjson::JsonValue;
 
 const DB_PATH: &str = "./likes.db";
 const LIKES_PATH: &str = "../likes5-simple.jsonl";
+const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
 
 const CHECKIN_STEP: u64 = 10_000;
-const SYNC_STEP: u64 = 100;
+/// how many entries go in each transaction with `--durability none`
+const UNSYNCED_COMMIT_STEP: u64 = 100;
 
 const MB_IN_KB: i64 = 2_i64.pow(10);
 const WRITE_CACHE: i64 = 100 * MB_IN_KB;
@@ -21,6 +28,48 @@ const ADD_STATEMENT: &str =
         ON CONFLICT DO UPDATE
         SET likes = likes || ';' || ?2";
 
+// for encodings with self-delimiting liker entries
+const ADD_CONCAT_STATEMENT: &str =
+    "INSERT INTO likes (uri, likes) VALUES (?1, ?2)
+        ON CONFLICT DO UPDATE
+        SET likes = likes || ?2";
+
+// for page headers and compressed values, which can't be appended in sql
+const GET_VALUE_STATEMENT: &str =
+    "SELECT cast(likes as BLOB) FROM likes WHERE uri = ?1";
+
+const SET_VALUE_STATEMENT: &str =
+    "INSERT INTO likes (uri, likes) VALUES (?1, ?2)
+        ON CONFLICT DO UPDATE
+        SET likes = ?2";
+
+const LISTING_STATEMENT: &str =
+    "INSERT INTO listing (uri, entry) VALUES (?1, ?2)
+        ON CONFLICT DO NOTHING";
+
+// did is the liker entry's did prefix, and rkey the rest of it
+const LIKED_STATEMENT: &str =
+    "INSERT INTO liked (uri, did, rkey) VALUES (?1, ?2, ?3)
+        ON CONFLICT DO NOTHING";
+
+const FORWARD_STATEMENT: &str =
+    "INSERT INTO forward (did, rkey, uri) VALUES (?1, ?2, ?3)
+        ON CONFLICT DO NOTHING";
+
+const UNFORWARD_STATEMENT: &str =
+    "DELETE FROM forward WHERE did = ?1 AND rkey = ?2";
+
+// counts live on the subject's row: its liker list, or its page header
+const COUNT_STATEMENT: &str =
+    "UPDATE likes SET count = count + ?2 WHERE uri = ?1";
+
+const LIKE_SUBJECT_STATEMENT: &str =
+    "INSERT INTO like_subjects (did_rkey, uri) VALUES (?1, ?2)
+        ON CONFLICT DO NOTHING";
+
+const TAKE_LIKE_SUBJECT_STATEMENT: &str =
+    "DELETE FROM like_subjects WHERE did_rkey = ?1 RETURNING uri";
+
 const DEL_STATEMENT: &str =
     "INSERT INTO unlikes (did_rkey) VALUES (?1)
         ON CONFLICT DO NOTHING";
@@ -44,18 +93,21 @@ struct CreateEntry {
     did: String,
     rkey: String,
     uri: String,
+    /// its subject key
+    key: Vec<u8>,
+    /// its `did!rkey`, as the format writes it
+    liker: Vec<u8>,
 }
 
 #[derive(Debug)]
 struct DeleteEntry {
     did: String,
-    rkey: String,
+    liker: Vec<u8>,
 }
 
-impl FromStr for Action {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self> {
+impl Action {
+    /// Parse an entry and make its keys, which the parser threads do
+    fn parse(format: Format, s: &str) -> Result<Self> {
         let parsed: JsonValue = s.parse()?;
         let entry = <Vec<_>>::try_from(parsed)?;
         if entry.len() != 4 {
@@ -67,26 +119,56 @@ impl FromStr for Action {
         match action.as_str() {
             "c" => {
                 let uri = String::try_from(entry[3].clone())?;
-                Ok(Action::Create(CreateEntry { did, rkey, uri }))
+                let (key, liker) = (format.subject_key(&uri), format.liker(&did, &rkey));
+                Ok(Action::Create(CreateEntry { did, rkey, uri, key, liker }))
             }
             "d" => {
-                Ok(Action::Delete(DeleteEntry { did, rkey }))
+                let liker = format.liker(&did, &rkey);
+                Ok(Action::Delete(DeleteEntry { did, liker }))
             }
             _ => Err(anyhow!("need 'c' or 'd' for entry action type"))
         }
     }
 }
 
-fn show_update(d: Duration, db_path: &Path, stats: &Stats) {
+fn show_update(d: Duration, db_path: &Path, stats: &Stats, reads: &Reads) {
     let Ok(size) = db_path.metadata().map(|m| m.len()) else {
         return
     };
-    println!("{}\t{}\t{:.3}", stats.entries, size, d.as_secs_f32());
+    println!("{}\t{}\t{:.3}{}", stats.entries, size, d.as_secs_f32(), reads.checkin(stats.entries));
+}
+
+/// Fetch a subject's whole liker list like `read` does, for `--readers`
+fn read_likers(conn: &Connection, format: Format, uri: &str) -> Result<()> {
+    let key = format.subject_key(uri);
+    let found = conn.prepare_cached(GET_VALUE_STATEMENT)?
+        .query_row((&key,), |row| row.get::<_, Vec<u8>>(0))
+        .optional()?;
+    if let (Some(found), Some(_)) = (found, format.paging) {
+        let header = Header::from_bytes(&found)?;
+        let (first, last) = paging::page_range(&key, &header);
+        let mut stmt = conn.prepare_cached(
+            "SELECT cast(likes as BLOB) FROM likes WHERE uri BETWEEN ?1 AND ?2 ORDER BY uri")?;
+        for page in stmt.query_map((first, last), |row| row.get::<_, Vec<u8>>(0))? {
+            page?;
+        }
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let reader = io::BufReader::new(File::open(LIKES_PATH)?);
 
+    let format = Format::from_args()?;
+    println!("{format}");
+    let mut codec = ValueCodec::from_args()?;
+    if let Some(codec) = &codec {
+        println!("values: {codec}");
+    }
+    let durability = Durability::from_args()?;
+    println!("durability: {durability}");
+    let add_sql = if format.encoding.separator().is_some() { ADD_STATEMENT } else { ADD_CONCAT_STATEMENT };
+
     let mut conn = Connection::open(DB_PATH)?;
     conn.pragma_update(None, "journal_mode", "WAL")?;
 
@@ -96,72 +178,214 @@ fn main() -> Result<()> {
     // wal_autocheckpoint: massive speedup up to ~5M entries, falling to no improvement by ~14M
     // threads: nothing measurable up to ~6.5M entries, ended test early
 
-    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    // commits reach the WAL through the OS but aren't synced, except for
+    // periodic syncs' and `sync`'s. it can't change inside a transaction, so
+    // it's set as the synced entry's transaction starts
+    conn.pragma_update(None, "synchronous", if durability == Durability::Sync { "FULL" } else { "OFF" })?;
     conn.pragma_update(None, "cache_size", (-WRITE_CACHE).to_string())?;
     conn.pragma_update(None, "busy_timeout", "100")?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS likes (
-            uri   blob PRIMARY KEY,
-            likes blob NOT NULL
-        )",
-        (),
-    ).expect("create likes table");
+    if format.counts {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS likes (
+                uri   blob PRIMARY KEY,
+                -- before likes, so reading it doesn't walk the list's overflow pages
+                count integer NOT NULL DEFAULT 0,
+                likes blob NOT NULL
+            )",
+            (),
+        ).expect("create counted likes table");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS like_subjects (
+                did_rkey blob PRIMARY KEY,
+                uri      blob NOT NULL
+            )",
+            (),
+        ).expect("create like_subjects table");
+    } else {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS likes (
+                uri   blob PRIMARY KEY,
+                likes blob NOT NULL
+            )",
+            (),
+        ).expect("create likes table");
+    }
     conn.execute(
         "CREATE TABLE IF NOT EXISTS unlikes (
             did_rkey blob PRIMARY KEY
         )",
         (),
     ).expect("create unlikes table");
+    if format.liked {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS liked (
+                uri  blob NOT NULL,
+                did  blob NOT NULL,
+                rkey blob NOT NULL,
+                PRIMARY KEY (uri, did, rkey)
+            ) WITHOUT ROWID",
+            (),
+        ).expect("create liked table");
+    }
+    if format.forward_index {
+        // did and rkey split like in liked
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS forward (
+                did  blob NOT NULL,
+                rkey blob NOT NULL,
+                uri  text NOT NULL,
+                PRIMARY KEY (did, rkey)
+            ) WITHOUT ROWID",
+            (),
+        ).expect("create forward table");
+    }
+    if format.listing {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS listing (
+                uri   blob NOT NULL,
+                entry blob NOT NULL,
+                PRIMARY KEY (uri, entry)
+            ) WITHOUT ROWID",
+            (),
+        ).expect("create listing table");
+    }
 
     let mut stats: Stats = Default::default();
     let t0 = Instant::now();
 
-    let mut tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
-    let mut add_statement = tx.prepare_cached(ADD_STATEMENT)?;
-    let mut del_statement = tx.prepare_cached(DEL_STATEMENT)?;
+    let readers = mixed::readers()?;
+    let parsers = pipeline::parsers()?;
+    mixed::run(readers, SUBJECTS_PATH, || {
+        // each reader gets its own connection, reading alongside the writer through the WAL
+        let conn = Connection::open(DB_PATH)?;
+        conn.pragma_update(None, "busy_timeout", "100")?;
+        Ok(move |uri: &str| read_likers(&conn, format, uri))
+    }, |reads| {
+        pipeline::run(reader, parsers, |line| Action::parse(format, line), |actions| {
+            let mut tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut add_statement = tx.prepare_cached(add_sql)?;
+            let mut del_statement = tx.prepare_cached(DEL_STATEMENT)?;
 
-    for line in reader.lines() {
-        let action: Action = line?.parse()?;
-        let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
-        let sync = (stats.entries % SYNC_STEP) == (SYNC_STEP - 1);
+            let mut syncs = durability.syncs();
+            let mut full = durability == Durability::Sync;
+            for action in actions {
+                let action = action?;
+                let checkin = (stats.entries % CHECKIN_STEP) == (CHECKIN_STEP - 1);
+                let sync = syncs.due();
+                // every entry but `none`'s gets its own transaction
+                let commit = match durability {
+                    Durability::None => (stats.entries % UNSYNCED_COMMIT_STEP) == (UNSYNCED_COMMIT_STEP - 1),
+                    _ => true,
+                };
 
-        if sync {
-            drop(add_statement);
-            drop(del_statement);
-            tx.commit()?;
-            tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
-            add_statement = tx.prepare_cached(ADD_STATEMENT)?;
-            del_statement = tx.prepare_cached(DEL_STATEMENT)?;
-        }
+                if commit {
+                    drop(add_statement);
+                    drop(del_statement);
+                    tx.commit()?;
+                    if sync != full && durability != Durability::Sync {
+                        conn.pragma_update(None, "synchronous", if sync { "FULL" } else { "OFF" })?;
+                        full = sync;
+                    }
+                    tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                    add_statement = tx.prepare_cached(add_sql)?;
+                    del_statement = tx.prepare_cached(DEL_STATEMENT)?;
+                }
 
-        match action {
-            Action::Create(entry) => {
-                let val = format!("{}!{}", entry.did, entry.rkey);
-                add_statement.execute((entry.uri.into_bytes(), val.into_bytes()))?;
-                stats.likes += 1;
-                // TODO: subjects. could get there with RETURNING but for now will just query at the end.
-                // https://sqlite.org/forum/info/e88687aeaecf9528
-            }
-            Action::Delete(entry) => {
-                let key = format!("{}!{}", entry.did, entry.rkey);
-                del_statement.execute((key.into_bytes(),))?;
-                stats.unlikes += 1;
+                match action {
+                    Action::Create(entry) => {
+                        let subject_key = entry.key;
+                        let mut key = subject_key.clone();
+                        if format.listing {
+                            tx.prepare_cached(LISTING_STATEMENT)?
+                                .execute((&key, listing::entry(&entry.did, &entry.rkey)))?;
+                        }
+                        let val = entry.liker;
+                        if let Some(paging) = format.paging {
+                            let header = tx.prepare_cached(GET_VALUE_STATEMENT)?
+                                .query_row((&key,), |row| row.get::<_, Vec<u8>>(0))
+                                .optional()?
+                                .map(|h| Header::from_bytes(&h))
+                                .transpose()?;
+                            if header.is_none() {
+                                stats.subjects += 1;
+                            }
+                            let (page, header) = paging.append(header);
+                            tx.prepare_cached(SET_VALUE_STATEMENT)?.execute((&key, &header.to_bytes()[..]))?;
+                            key = paging::page_key(&key, page);
+                        }
+                        if let Some(codec) = codec.as_mut() {
+                            let existing = tx.prepare_cached(GET_VALUE_STATEMENT)?
+                                .query_row((&key,), |row| row.get::<_, Vec<u8>>(0))
+                                .optional()?
+                                .map(|v| codec.decompress(&v))
+                                .transpose()?;
+                            let joined = format.encoding.join(existing.as_deref(), [&*val]);
+                            tx.prepare_cached(SET_VALUE_STATEMENT)?.execute((&key, codec.compress(&joined)?))?;
+                        } else {
+                            add_statement.execute((key, &val))?;
+                        }
+                        let did = format.encoding.liker_prefix(&entry.did);
+                        let liker = val;
+                        if format.liked {
+                            tx.prepare_cached(LIKED_STATEMENT)?.execute((&subject_key, &did, &liker[did.len()..]))?;
+                        }
+                        if format.forward_index {
+                            tx.prepare_cached(FORWARD_STATEMENT)?.execute((&did, &liker[did.len()..], &entry.uri))?;
+                        }
+                        if format.counts {
+                            tx.prepare_cached(COUNT_STATEMENT)?.execute((&subject_key, 1))?;
+                            tx.prepare_cached(LIKE_SUBJECT_STATEMENT)?
+                                .execute((&liker, &subject_key))?;
+                        }
+                        stats.likes += 1;
+                        // TODO: subjects. could get there with RETURNING but for now will just query at the end.
+                        // https://sqlite.org/forum/info/e88687aeaecf9528
+                    }
+                    Action::Delete(entry) => {
+                        let key = entry.liker;
+                        del_statement.execute((&key,))?;
+                        if format.forward_index {
+                            let did = format.encoding.liker_prefix(&entry.did);
+                            tx.prepare_cached(UNFORWARD_STATEMENT)?.execute((&did, &key[did.len()..]))?;
+                        }
+                        if format.counts {
+                            // likes from before the data started have no subject to take from
+                            let subject = tx.prepare_cached(TAKE_LIKE_SUBJECT_STATEMENT)?
+                                .query_row((&key,), |row| row.get::<_, Vec<u8>>(0))
+                                .optional()?;
+                            if let Some(subject) = subject {
+                                tx.prepare_cached(COUNT_STATEMENT)?.execute((subject, -1))?;
+                            }
+                        }
+                        stats.unlikes += 1;
+                    }
+                }
+                stats.entries += 1;
+
+                if checkin {
+                    reads.check()?;
+                    show_update(t0.elapsed(), DB_PATH.as_ref(), &stats, reads);
+                }
             }
-        }
-        stats.entries += 1;
 
-        if checkin {
-            show_update(t0.elapsed(), DB_PATH.as_ref(), &stats);
-        }
+            drop(add_statement);
+            drop(del_statement);
+            tx.commit()?;
+            Ok(())
+        })
+    })?;
+    // the last commit went in with whatever was set, so sync what's still
+    // unsynced with a checkpoint, as the other backends do at the end
+    if durability != Durability::Sync {
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        conn.execute_batch("PRAGMA wal_checkpoint(FULL)")?;
     }
 
-    drop(add_statement);
-    drop(del_statement);
-    tx.commit()?;
-
     let d = t0.elapsed();
 
-    stats.subjects = conn.query_row("SELECT count(*) FROM likes", [], |r| r.get(0))?;
+    if format.paging.is_none() {
+        stats.subjects = conn.query_row("SELECT count(*) FROM likes", [], |r| r.get(0))?;
+    }
 
     println!("done in {:.1}s. entries: {}, likes: {}, unlikes: {}, subjects: {}",
         d.as_secs_f32(), stats.entries, stats.likes, stats.unlikes, stats.subjects);
@@ -1,10 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
-use rusqlite::Connection;
+use kv_for_likes_common::{authority, bench, cli, forward, Encoding, Format};
+use kv_for_likes_common::bench::{Export, Mode};
+use kv_for_likes_common::histogram::Timings;
+use kv_for_likes_common::dict::ValueCodec;
+use kv_for_likes_common::listing::{self, Cursor, Order, Page};
+use kv_for_likes_common::paging::{self, Header};
+use kv_for_likes_common::phase::{self, CacheCounters, Phases};
+use kv_for_likes_common::verify::{self, Mismatches};
+use rusqlite::{ffi, params_from_iter, Connection, OptionalExtension, Statement};
 
 const DB_PATH: &str = "./likes.db";
 const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
@@ -12,6 +20,46 @@ const SUBJECTS_PATH: &str = "../sampled-subjects-100k.txt";
 const MB_IN_KB: i64 = 2_i64.pow(10);
 const READ_CACHE: i64 = 64 * MB_IN_KB;
 
+/// sqlite's old default limit on bound parameters
+const BATCH_PARAMS: usize = 999;
+
+const LIST_OLDEST_STATEMENT: &str =
+    "SELECT entry FROM listing WHERE uri = ?1 ORDER BY entry LIMIT ?2";
+
+const LIST_OLDEST_AFTER_STATEMENT: &str =
+    "SELECT entry FROM listing WHERE uri = ?1 AND entry > ?3 ORDER BY entry LIMIT ?2";
+
+const LIST_NEWEST_STATEMENT: &str =
+    "SELECT entry FROM listing WHERE uri = ?1 ORDER BY entry DESC LIMIT ?2";
+
+const LIST_NEWEST_BEFORE_STATEMENT: &str =
+    "SELECT entry FROM listing WHERE uri = ?1 AND entry < ?3 ORDER BY entry DESC LIMIT ?2";
+
+const FORWARD_OLDEST_STATEMENT: &str =
+    "SELECT rkey, uri FROM forward WHERE did = ?1 ORDER BY rkey LIMIT ?2";
+
+const FORWARD_OLDEST_AFTER_STATEMENT: &str =
+    "SELECT rkey, uri FROM forward WHERE did = ?1 AND rkey > ?3 ORDER BY rkey LIMIT ?2";
+
+const FORWARD_NEWEST_STATEMENT: &str =
+    "SELECT rkey, uri FROM forward WHERE did = ?1 ORDER BY rkey DESC LIMIT ?2";
+
+const FORWARD_NEWEST_BEFORE_STATEMENT: &str =
+    "SELECT rkey, uri FROM forward WHERE did = ?1 AND rkey < ?3 ORDER BY rkey DESC LIMIT ?2";
+
+// a range rather than `LIKE 'at://did/%'`, since subject keys are blobs and
+// not always text
+const AUTHORITY_STATEMENT: &str =
+    "SELECT uri, cast(likes as BLOB) FROM likes WHERE uri >= ?1 AND uri < ?2 ORDER BY uri";
+
+// a like only counts if its liker entry (did || rkey) was never unliked
+const HAS_LIKED_STATEMENT: &str =
+    "SELECT EXISTS (
+        SELECT 1 FROM liked
+        WHERE uri = ?1 AND did = ?2
+            AND NOT EXISTS (SELECT 1 FROM unlikes WHERE did_rkey = cast(liked.did || liked.rkey as BLOB))
+    )";
+
 #[derive(Debug)]
 struct Subject {
     uri: String,
@@ -29,44 +77,296 @@ impl FromStr for Subject {
     }
 }
 
+/// A subject's whole liker list, streaming its pages in order if it's paged
+fn get_likers(conn: &Connection, format: Format, mut codec: Option<&mut ValueCodec>, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut value = |v: Vec<u8>| match codec.as_mut() {
+        Some(codec) => codec.decompress(&v),
+        None => Ok(v),
+    };
+    let Some(found) = conn
+        .prepare_cached("SELECT cast(likes as BLOB) FROM likes WHERE uri = ?1")?
+        .query_row((key,), |row| row.get::<_, Vec<u8>>(0))
+        .optional()? else {
+        return Ok(None)
+    };
+    if format.paging.is_none() {
+        return value(found).map(Some)
+    }
+    let header = Header::from_bytes(&found)?;
+    let (first, last) = paging::page_range(key, &header);
+    let mut stmt = conn.prepare_cached(
+        "SELECT cast(likes as BLOB) FROM likes WHERE uri BETWEEN ?1 AND ?2 ORDER BY uri")?;
+    let pages = stmt
+        .query_map((first, last), |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .map(value)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(format.encoding.join(None, pages.iter().map(|p| &p[..]))))
+}
+
+/// `SELECT uri, likes` for `n` keys at once
+fn batch_statement(n: usize) -> String {
+    let params = (1..=n).map(|i| format!("?{i}")).collect::<Vec<_>>().join(", ");
+    format!("SELECT uri, cast(likes as BLOB) FROM likes WHERE uri IN ({params})")
+}
+
+/// The rows found for some keys, with `IN (...)` queries of up to
+/// `BATCH_PARAMS` keys each
+fn get_rows(conn: &Connection, keys: &[Vec<u8>]) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+    let mut rows = HashMap::new();
+    for keys in keys.chunks(BATCH_PARAMS) {
+        let mut stmt = conn.prepare_cached(&batch_statement(keys.len()))?;
+        let found = stmt.query_map(params_from_iter(keys), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in found {
+            let (key, value) = row?;
+            rows.insert(key, value);
+        }
+    }
+    Ok(rows)
+}
+
+/// Each subject's whole liker list, from one query for the subjects and, if
+/// they're paged, one more for all of their pages
+fn get_batch(
+    conn: &Connection,
+    format: Format,
+    mut codec: Option<&mut ValueCodec>,
+    uris: &[&str],
+) -> Result<Vec<Option<Vec<u8>>>> {
+    let mut value = |v: Vec<u8>| match codec.as_mut() {
+        Some(codec) => codec.decompress(&v),
+        None => Ok(v),
+    };
+    let keys: Vec<_> = uris.iter().map(|uri| format.subject_key(uri)).collect();
+    // a uri can be in a batch more than once, so rows are copied out, not taken
+    let found = get_rows(conn, &keys)?;
+    if format.paging.is_none() {
+        return keys.iter().map(|key| found.get(key).cloned().map(&mut value).transpose()).collect()
+    }
+    let mut page_keys = vec![];
+    for key in &keys {
+        let Some(header) = found.get(key) else {
+            page_keys.push(None);
+            continue
+        };
+        let header = Header::from_bytes(header)?;
+        page_keys.push(Some((0..header.pages).map(|page| paging::page_key(key, page)).collect::<Vec<_>>()));
+    }
+    let pages = get_rows(conn, &page_keys.iter().flatten().flatten().cloned().collect::<Vec<_>>())?;
+    page_keys
+        .into_iter()
+        .map(|keys| keys.map(|keys| {
+            let likers = keys
+                .iter()
+                .map(|key| value(pages.get(key).cloned().ok_or_else(|| anyhow!("missing a page"))?))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(format.encoding.join(None, likers.iter().map(|p| &p[..])))
+        }).transpose())
+        .collect()
+}
+
+/// Every like on the records of a did, streamed from a range over the prefix
+/// with an [`AUTHORITY_STATEMENT`]
+fn likes_for_authority<'a>(
+    stmt: &'a mut Statement,
+    format: Format,
+    mut codec: Option<&'a mut ValueCodec>,
+    did: &str,
+    collection: Option<&'a str>,
+) -> Result<impl Iterator<Item = Result<authority::Like>> + 'a> {
+    let prefix = authority::prefix(format, did, collection)?;
+    let end = listing::after_prefix(&prefix);
+    let rows = stmt
+        .query_map((&prefix, end), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .map(|row| Ok(row?));
+    Ok(authority::likes(format, prefix, collection, rows, move |v| match codec.as_mut() {
+        Some(codec) => codec.decompress(&v),
+        None => Ok(v),
+    }))
+}
+
+/// One page of a subject's likers from the listing table
+fn list_likers(conn: &Connection, key: &[u8], order: Order, limit: usize, cursor: Option<&Cursor>) -> Result<Page> {
+    // one extra, to know if there's another page
+    let fetch = limit as i64 + 1;
+    let entries = match (order, cursor) {
+        (Order::OldestFirst, None) => conn.prepare_cached(LIST_OLDEST_STATEMENT)?
+            .query_map((key, fetch), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?,
+        (Order::OldestFirst, Some(cursor)) => conn.prepare_cached(LIST_OLDEST_AFTER_STATEMENT)?
+            .query_map((key, fetch, cursor.entry()), |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?,
+        (Order::NewestFirst, None) => conn.prepare_cached(LIST_NEWEST_STATEMENT)?
+            .query_map((key, fetch), |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?,
+        (Order::NewestFirst, Some(cursor)) => conn.prepare_cached(LIST_NEWEST_BEFORE_STATEMENT)?
+            .query_map((key, fetch, cursor.entry()), |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?,
+    };
+    listing::page_from_entries(entries.into_iter().map(Ok), limit)
+}
+
+/// One page of a did's likes from the forward table
+fn list_liked(
+    conn: &Connection,
+    encoding: Encoding,
+    did: &str,
+    order: Order,
+    limit: usize,
+    cursor: Option<&Cursor>,
+) -> Result<forward::Page> {
+    let did = encoding.liker_prefix(did);
+    // one extra, to know if there's another page
+    let fetch = limit as i64 + 1;
+    let row = |row: &rusqlite::Row| -> rusqlite::Result<(Vec<u8>, Vec<u8>)> {
+        Ok((row.get(0)?, row.get::<_, String>(1)?.into_bytes()))
+    };
+    let entries = match (order, cursor) {
+        (Order::OldestFirst, None) => conn.prepare_cached(FORWARD_OLDEST_STATEMENT)?
+            .query_map((&did, fetch), row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        (Order::OldestFirst, Some(cursor)) => conn.prepare_cached(FORWARD_OLDEST_AFTER_STATEMENT)?
+            .query_map((&did, fetch, cursor.entry()), row)?
+            .collect::<rusqlite::Result<_>>()?,
+        (Order::NewestFirst, None) => conn.prepare_cached(FORWARD_NEWEST_STATEMENT)?
+            .query_map((&did, fetch), row)?
+            .collect::<rusqlite::Result<_>>()?,
+        (Order::NewestFirst, Some(cursor)) => conn.prepare_cached(FORWARD_NEWEST_BEFORE_STATEMENT)?
+            .query_map((&did, fetch, cursor.entry()), row)?
+            .collect::<rusqlite::Result<_>>()?,
+    };
+    forward::page_from_entries(encoding, entries.into_iter().map(Ok), limit)
+}
+
+/// The connection's page cache hits and misses since it opened
+fn cache_counters(conn: &Connection) -> Result<CacheCounters> {
+    let status = |op| -> Result<u64> {
+        let (mut current, mut highwater) = (0, 0);
+        // SAFETY: the handle stays open while `conn` is borrowed, and this only reads counters from it
+        let rc = unsafe { ffi::sqlite3_db_status(conn.handle(), op, &mut current, &mut highwater, 0) };
+        if rc != ffi::SQLITE_OK {
+            return Err(anyhow!("sqlite3_db_status failed with {rc}"))
+        }
+        Ok(current as u64)
+    };
+    Ok(CacheCounters {
+        hits: status(ffi::SQLITE_DBSTATUS_CACHE_HIT)?,
+        misses: status(ffi::SQLITE_DBSTATUS_CACHE_MISS)?,
+    })
+}
+
 fn main() -> Result<()> {
+    let format = Format::from_args()?;
+    println!("{format}");
+    let mut codec = ValueCodec::from_args()?;
+    if let Some(codec) = &codec {
+        println!("values: {codec}");
+    }
+
+    let mode: Mode = cli::opt_or_default("mode")?;
+    println!("mode: {mode}");
+    let sampled_unlikes = match mode {
+        Mode::Forward | Mode::HasLiked => {
+            // from a throwaway open, so the timed one starts cold
+            let conn = Connection::open(DB_PATH)?;
+            let mut stmt = conn.prepare("SELECT 1 FROM unlikes WHERE did_rkey = ?1")?;
+            bench::sampled_unlikes(SUBJECTS_PATH, format, |liker| Ok(stmt.exists((liker,))?))?
+        }
+        _ => HashSet::new(),
+    };
+
+    phase::drop_page_cache();
     let conn = Connection::open(DB_PATH)?;
     conn.pragma_update(None, "cache_size", (-READ_CACHE).to_string())?;
+    let phases = Phases::from_args(|| Ok(Some(cache_counters(&conn)?)))?;
 
-    let mut stmt = conn.prepare("SELECT cast(likes as TEXT) FROM likes WHERE uri = ?1")?;
+    if mode == Mode::List {
+        return bench::run_list(SUBJECTS_PATH, format, &phases, |key, order, limit, cursor| {
+            list_likers(&conn, key, order, limit, cursor)
+        })
+    }
+    if mode == Mode::Forward {
+        return bench::run_forward(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |did, order, limit, cursor| list_liked(&conn, format.encoding, did, order, limit, cursor),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::HasLiked {
+        if !format.liked {
+            return Err(anyhow!("has-liked mode needs a db ingested with --liked (and --liked here too)"))
+        }
+        return bench::run_has_liked(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |uri, did| Ok(conn
+                .prepare_cached(HAS_LIKED_STATEMENT)?
+                .query_row((format.subject_key(uri), format.encoding.liker_prefix(did)), |row| row.get(0))?),
+            &sampled_unlikes,
+        )
+    }
+    if mode == Mode::Batch {
+        return bench::run_batch(SUBJECTS_PATH, format, &phases, |uris| get_batch(&conn, format, codec.as_mut(), uris))
+    }
+    if mode == Mode::Authority {
+        return bench::run_authority(SUBJECTS_PATH, format, &phases, |did, collection| {
+            let mut stmt = conn.prepare_cached(AUTHORITY_STATEMENT)?;
+            let likes = likes_for_authority(&mut stmt, format, codec.as_mut(), did, collection)?.collect();
+            likes
+        })
+    }
+    if mode == Mode::Count {
+        return bench::run_count(
+            SUBJECTS_PATH,
+            format,
+            &phases,
+            |key| Ok(conn
+                .prepare_cached("SELECT count FROM likes WHERE uri = ?1")?
+                .query_row((key,), |row| row.get(0))
+                .optional()?
+                .unwrap_or(0)),
+            |liker| Ok(conn
+                .prepare_cached("SELECT 1 FROM unlikes WHERE did_rkey = ?1")?
+                .exists((liker,))?),
+        )
+    }
 
-    println!("loop\tduration");
-    for n in 0..=2 {
+    let mut export = Export::from_args(Mode::Full)?;
+    let mut mismatched = 0;
+    println!("pass\tphase\tduration");
+    for (n, phase) in phases.passes() {
         let reader = io::BufReader::new(File::open(SUBJECTS_PATH)?);
+        let mut mismatches = Mismatches::default();
 
         let mut total = Duration::from_secs(0);
-        let mut times: HashMap<usize, Vec<f64>> = HashMap::new();
+        let mut times = Timings::default();
 
         for line in reader.lines() {
             let subject: Subject = line?.parse()?;
             let likes = subject.likers.split(';').count();
 
+            let key = format.subject_key(&subject.uri);
+
             let t0 = Instant::now();
-            let db_likers: String = stmt.query_row((subject.uri.into_bytes(),), |row| row.get(0))?;
+            let db_likers = get_likers(&conn, format, codec.as_mut(), &key)?;
             let d = t0.elapsed();
 
             total += d;
-            (*times.entry(likes).or_insert(vec![])).push(d.as_nanos() as f64);
+            times.record(likes, d);
 
-            assert_eq!(db_likers, subject.likers);
-        }
-        println!("{n}\t{:.3}", total.as_secs_f32());
-
-        let mut res: Vec<_> = times
-            .iter()
-            .map(|(likes, group)|
-                (likes, group.into_iter().sum::<f64>() / (group.len() as f64) / 1000.0))
-            .collect();
-        res.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        for (likes, micros) in res {
-            println!("{likes}\t{micros:.3}");
+            mismatches.record(&subject.uri, verify::classify_likers(format.encoding, db_likers.as_deref(), &subject.likers)?);
         }
+        println!("{n}\t{phase}\t{:.3}", total.as_secs_f32());
+        phases.report()?;
+        mismatches.print();
+        mismatched += mismatches.len();
+        export.push(phase, vec![("reads", times)]);
     }
 
-    Ok(())
+    export.write()?;
+    verify::outcome(mismatched)
 }
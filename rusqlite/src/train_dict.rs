@@ -0,0 +1,33 @@
+use std::time::Instant;
+use anyhow::Result;
+use kv_for_likes_common::{cli, dict};
+use kv_for_likes_common::paging::HEADER_LEN;
+use rusqlite::Connection;
+
+const DB_PATH: &str = "./likes.db";
+
+/// Sample liker values from an ingested db (built without `--dict`) and train
+/// a zstd dictionary for `--dict`
+fn main() -> Result<()> {
+    let want: usize = cli::opt("samples")?.unwrap_or(dict::DEFAULT_SAMPLES);
+    let dict_size: usize = cli::opt("dict-size")?.unwrap_or(dict::DEFAULT_DICT_SIZE);
+    let out: String = cli::opt("out")?.unwrap_or(dict::DEFAULT_DICT_PATH.to_string());
+
+    let conn = Connection::open(DB_PATH)?;
+    let total: u64 = conn.query_row("SELECT count(*) FROM likes", [], |r| r.get(0))?;
+    let stride = dict::sample_stride(total, want);
+
+    // page headers are exactly HEADER_LEN, and even one compact liker is longer
+    let samples = conn
+        .prepare("SELECT cast(likes as BLOB) FROM likes WHERE rowid % ?1 = 0 AND length(likes) != ?2")?
+        .query_map((stride, HEADER_LEN), |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let t0 = Instant::now();
+    let trained = dict::train(&samples, dict_size)?;
+    std::fs::write(&out, &trained)?;
+    println!("trained a {} byte dictionary from {} values in {:.1}s: {out}",
+        trained.len(), samples.len(), t0.elapsed().as_secs_f32());
+
+    Ok(())
+}